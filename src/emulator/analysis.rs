@@ -0,0 +1,405 @@
+use crate::emulator::consts;
+use crate::emulator::processor::{Instruction, Processor};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Call,
+    Return,
+    Skip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Warning {
+    // A jump, call or fallthrough leaves the loaded ROM.
+    OutOfBounds { at: u16, target: usize },
+    // An FX33/FX55 stores over bytes that are also decoded as instructions.
+    SelfModifying { at: u16, target: u16 },
+}
+
+#[derive(Debug)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<(u16, Instruction)>,
+}
+
+impl BasicBlock {
+    // The address after the block, which is past the address space for a
+    // block ending at FFFE.
+    pub fn end(&self) -> usize {
+        self.start as usize + 2 * self.instructions.len()
+    }
+
+    fn last(&self) -> Option<&(u16, Instruction)> {
+        self.instructions.last()
+    }
+}
+
+#[derive(Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    pub edges: Vec<Edge>,
+    pub indirect_jumps: Vec<u16>,
+    pub warnings: Vec<Warning>,
+}
+
+impl ControlFlowGraph {
+    pub fn build(rom: &[u8]) -> ControlFlowGraph {
+        let rom_start = consts::ROM_START as u16;
+        // Addresses are computed as usize so the last instruction's
+        // successors can't wrap around.
+        let rom_end = (consts::ROM_START + rom.len()).min(consts::XO_MEMORY_SIZE);
+        let fetch = |addr: usize| -> Option<Instruction> {
+            if addr < consts::ROM_START || addr + 2 > rom_end {
+                return None;
+            }
+            let offset = addr - consts::ROM_START;
+            let opcode = ((rom[offset] as u16) << 8) | rom[offset + 1] as u16;
+            Some(Processor::decode_instruction(opcode))
+        };
+
+        let mut reachable = BTreeSet::new();
+        let mut leaders = BTreeSet::from([rom_start]);
+        let mut call_targets = BTreeSet::new();
+        let mut warnings = Vec::new();
+        let mut worklist = vec![rom_start];
+
+        while let Some(addr) = worklist.pop() {
+            if reachable.contains(&addr) {
+                continue;
+            }
+            let Some(instruction) = fetch(addr as usize) else {
+                continue;
+            };
+            reachable.insert(addr);
+
+            let (targets, ends_block) = ControlFlowGraph::successors(addr, instruction);
+            if let Instruction::CALL(target) = instruction {
+                call_targets.insert(target);
+            }
+
+            for (target, _) in targets {
+                if fetch(target).is_none() {
+                    warnings.push(Warning::OutOfBounds { at: addr, target });
+                    continue;
+                }
+                // Fetched, so within the address space.
+                let target = target as u16;
+                if ends_block {
+                    leaders.insert(target);
+                }
+                worklist.push(target);
+            }
+        }
+
+        let mut blocks = BTreeMap::new();
+        let mut edges = Vec::new();
+        let mut indirect_jumps = Vec::new();
+
+        for &start in leaders.iter().filter(|addr| reachable.contains(addr)) {
+            let mut block = BasicBlock {
+                start,
+                instructions: Vec::new(),
+            };
+            let mut addr = start;
+
+            while let Some(instruction) = fetch(addr as usize) {
+                block.instructions.push((addr, instruction));
+
+                let (targets, ends_block) = ControlFlowGraph::successors(addr, instruction);
+                if ends_block {
+                    if let Instruction::JUMPV0(_) = instruction {
+                        indirect_jumps.push(addr);
+                    }
+                    for (target, kind) in targets {
+                        let Ok(target) = u16::try_from(target) else {
+                            continue;
+                        };
+                        if reachable.contains(&target) {
+                            edges.push(Edge {
+                                from: start,
+                                to: target,
+                                kind,
+                            });
+                        }
+                    }
+                    break;
+                }
+
+                let Some(next) = addr.checked_add(2) else {
+                    break;
+                };
+                addr = next;
+                if leaders.contains(&addr) || !reachable.contains(&addr) {
+                    if reachable.contains(&addr) {
+                        edges.push(Edge {
+                            from: start,
+                            to: addr,
+                            kind: EdgeKind::Fallthrough,
+                        });
+                    }
+                    break;
+                }
+            }
+
+            blocks.insert(start, block);
+        }
+
+        let mut cfg = ControlFlowGraph {
+            blocks,
+            edges,
+            indirect_jumps,
+            warnings,
+        };
+        cfg.link_returns(&call_targets);
+        cfg.find_self_modifying_writes(&reachable);
+        cfg
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph rom {\n    node [shape=box, fontname=monospace];\n");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for (addr, instruction) in &block.instructions {
+                write!(label, "{:03X}: {:?}\\l", addr, instruction).unwrap();
+            }
+            let style = if self.indirect_jumps.contains(&block.last().unwrap().0) {
+                ", color=red"
+            } else {
+                ""
+            };
+            writeln!(
+                dot,
+                "    b{:03X} [label=\"{}\"{}];",
+                block.start, label, style
+            )
+            .unwrap();
+        }
+
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Call => " [label=\"call\", style=bold]",
+                EdgeKind::Return => " [label=\"ret\", style=dashed]",
+                EdgeKind::Skip => " [label=\"skip\"]",
+            };
+            writeln!(dot, "    b{:03X} -> b{:03X}{};", edge.from, edge.to, style).unwrap();
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn successors(addr: u16, instruction: Instruction) -> (Vec<(usize, EdgeKind)>, bool) {
+        let addr = addr as usize;
+        match instruction {
+            Instruction::JUMP(target) => (vec![(target as usize, EdgeKind::Jump)], true),
+            Instruction::CALL(target) => (
+                vec![
+                    (target as usize, EdgeKind::Call),
+                    (addr + 2, EdgeKind::Fallthrough),
+                ],
+                true,
+            ),
+            Instruction::RET | Instruction::EXIT | Instruction::JUMPV0(_) => (vec![], true),
+            Instruction::SE(..)
+            | Instruction::SNE(..)
+            | Instruction::SER(..)
+            | Instruction::SNER(..)
+            | Instruction::SKP(_)
+            | Instruction::SKNP(_) => (
+                vec![
+                    (addr + 2, EdgeKind::Fallthrough),
+                    (addr + 4, EdgeKind::Skip),
+                ],
+                true,
+            ),
+            _ => (vec![(addr + 2, EdgeKind::Fallthrough)], false),
+        }
+    }
+
+    // Each RET is linked to the return sites of every CALL whose subroutine
+    // reaches it without passing through another CALL.
+    fn link_returns(&mut self, call_targets: &BTreeSet<u16>) {
+        let mut return_edges = Vec::new();
+
+        for &target in call_targets {
+            let return_sites: Vec<u16> = self
+                .edges
+                .iter()
+                .filter(|edge| edge.kind == EdgeKind::Call && edge.to == target)
+                .filter_map(|edge| self.blocks.get(&edge.from))
+                .filter_map(|block| u16::try_from(block.end()).ok())
+                .collect();
+
+            let mut visited = BTreeSet::new();
+            let mut worklist = vec![target];
+            while let Some(start) = worklist.pop() {
+                if !visited.insert(start) {
+                    continue;
+                }
+                let Some(block) = self.blocks.get(&start) else {
+                    continue;
+                };
+
+                match block.last() {
+                    Some((_, Instruction::RET)) => {
+                        for &site in &return_sites {
+                            return_edges.push(Edge {
+                                from: start,
+                                to: site,
+                                kind: EdgeKind::Return,
+                            });
+                        }
+                    }
+                    Some((_, Instruction::CALL(_))) => {
+                        worklist.extend(u16::try_from(block.end()).ok())
+                    }
+                    _ => worklist.extend(
+                        self.edges
+                            .iter()
+                            .filter(|edge| edge.from == start && edge.kind != EdgeKind::Call)
+                            .map(|edge| edge.to),
+                    ),
+                }
+            }
+        }
+
+        for edge in return_edges {
+            if !self.edges.contains(&edge) {
+                self.edges.push(edge);
+            }
+        }
+    }
+
+    // Only stores whose index comes from an ANNN earlier in the same block can
+    // be resolved statically.
+    fn find_self_modifying_writes(&mut self, reachable: &BTreeSet<u16>) {
+        for block in self.blocks.values() {
+            let mut index = None;
+
+            for &(addr, instruction) in &block.instructions {
+                let written = match instruction {
+                    Instruction::LDA(value) => {
+                        index = Some(value);
+                        continue;
+                    }
+                    Instruction::ADDI(_) | Instruction::LDSPR(_) => {
+                        index = None;
+                        continue;
+                    }
+                    Instruction::STBCD(_) => 3,
+                    Instruction::STREG(reg) => reg as u16 + 1,
+                    _ => continue,
+                };
+
+                let Some(start) = index else {
+                    continue;
+                };
+                for target in start..start + written {
+                    if reachable.contains(&target) || reachable.contains(&(target.wrapping_sub(1)))
+                    {
+                        self.warnings
+                            .push(Warning::SelfModifying { at: addr, target });
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn builds_blocks_and_edges() {
+        // 200: LD V0, 1
+        // 202: CALL 208
+        // 204: SE V0, 1
+        // 206: JUMP 204
+        // 208: RET
+        let rom = [0x60, 0x01, 0x22, 0x08, 0x30, 0x01, 0x12, 0x04, 0x00, 0xEE];
+        let cfg = ControlFlowGraph::build(&rom);
+
+        assert_eq!(
+            cfg.blocks.keys().copied().collect::<Vec<_>>(),
+            vec![0x200, 0x204, 0x206, 0x208]
+        );
+        assert!(cfg.edges.contains(&Edge {
+            from: 0x200,
+            to: 0x208,
+            kind: EdgeKind::Call
+        }));
+        assert!(cfg.edges.contains(&Edge {
+            from: 0x208,
+            to: 0x204,
+            kind: EdgeKind::Return
+        }));
+        assert!(cfg.edges.contains(&Edge {
+            from: 0x204,
+            to: 0x208,
+            kind: EdgeKind::Skip
+        }));
+        assert!(cfg.warnings.is_empty());
+    }
+
+    #[test]
+    fn stops_at_the_end_of_the_address_space() {
+        // Falls through to FFFE, one instruction short of wrapping around.
+        let rom = vec![0x80; 0x10000];
+        let cfg = ControlFlowGraph::build(&rom);
+
+        assert_eq!(cfg.blocks.keys().copied().collect::<Vec<_>>(), vec![0x200]);
+        assert_eq!(cfg.blocks[&0x200].end(), 0x10000);
+        assert_eq!(
+            cfg.warnings,
+            vec![Warning::OutOfBounds {
+                at: 0xFFFE,
+                target: 0x10000
+            }]
+        );
+    }
+
+    #[test]
+    fn detects_indirect_jumps_and_self_modifying_code() {
+        // 200: LD I, 206
+        // 202: STREG V1
+        // 204: JUMPV0 300
+        // 206: CLS
+        let rom = [0xA2, 0x06, 0xF1, 0x55, 0xB3, 0x00, 0x00, 0xE0];
+        let cfg = ControlFlowGraph::build(&rom);
+
+        assert_eq!(cfg.indirect_jumps, vec![0x204]);
+        assert!(cfg.to_dot().contains("color=red"));
+        // 206 itself is unreachable, so only data is written here.
+        assert!(cfg.warnings.is_empty());
+
+        // 200: LD I, 204
+        // 202: STREG V0
+        // 204: JUMP 200
+        let rom = [0xA2, 0x04, 0xF0, 0x55, 0x12, 0x00];
+        let cfg = ControlFlowGraph::build(&rom);
+        assert_eq!(
+            cfg.warnings,
+            vec![Warning::SelfModifying {
+                at: 0x202,
+                target: 0x204
+            }]
+        );
+    }
+}
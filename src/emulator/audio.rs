@@ -0,0 +1,33 @@
+use crate::emulator::consts;
+
+// A square wave generator for the CHIP-8 beeper. The phase carries over
+// between frames so a beep spanning several of them has no clicks.
+#[derive(Debug, Default)]
+pub struct Beeper {
+    phase: u32,
+}
+
+impl Beeper {
+    pub fn new() -> Beeper {
+        Beeper::default()
+    }
+
+    // Mono samples covering one frame at FRAME_RATE.
+    pub fn frame_samples(&mut self, active: bool) -> Vec<i16> {
+        if !active {
+            self.phase = 0;
+            return vec![0; consts::AUDIO_FRAME_SAMPLES];
+        }
+
+        (0..consts::AUDIO_FRAME_SAMPLES)
+            .map(|_| {
+                self.phase = (self.phase + consts::BEEP_FREQUENCY) % consts::AUDIO_SAMPLE_RATE;
+                if self.phase < consts::AUDIO_SAMPLE_RATE / 2 {
+                    consts::BEEP_VOLUME
+                } else {
+                    -consts::BEEP_VOLUME
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,215 @@
+use crate::emulator::consts;
+use crate::emulator::octo;
+use crate::emulator::processor::{ProcessorError, Quirks};
+use crate::emulator::rom::{Rom, RomFormat};
+use crate::emulator::rom_db::{self, RomSettings};
+use serde::Deserialize;
+use std::io::Read;
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+struct Payload {
+    program: String,
+    #[serde(default)]
+    options: OctoOptions,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct OctoOptions {
+    pub tickrate: Option<usize>,
+    pub fill_color: Option<String>,
+    pub background_color: Option<String>,
+    pub shift_quirks: bool,
+    pub load_store_quirks: bool,
+    pub clip_quirks: bool,
+    pub jump_quirks: bool,
+    pub v_blank_quirks: bool,
+    pub logic_quirks: bool,
+    pub max_size: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cartridge {
+    pub name: String,
+    pub source: String,
+    pub options: OctoOptions,
+}
+
+impl Cartridge {
+    pub fn from_path(path: &str) -> Result<Cartridge, ProcessorError> {
+        let file = std::fs::File::open(path).map_err(ProcessorError::IoError)?;
+        let name = Path::new(path)
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Cartridge::from_gif(&name, file)
+    }
+
+    // Every pixel of every frame carries two bits of the payload in the low
+    // bits of its palette index, most significant pair first. The payload is a
+    // 32-bit big-endian length followed by a JSON object holding the Octo
+    // source and its options.
+    pub fn from_gif<R: Read>(name: &str, reader: R) -> Result<Cartridge, ProcessorError> {
+        let mut decoder = gif::DecodeOptions::new()
+            .read_info(reader)
+            .map_err(|err| ProcessorError::InvalidCartridge(err.to_string()))?;
+
+        let mut pixels = Vec::new();
+        while let Some(frame) = decoder
+            .read_next_frame()
+            .map_err(|err| ProcessorError::InvalidCartridge(err.to_string()))?
+        {
+            pixels.extend_from_slice(&frame.buffer);
+        }
+
+        let bytes: Vec<u8> = pixels
+            .chunks_exact(4)
+            .map(|p| (p[0] & 3) << 6 | (p[1] & 3) << 4 | (p[2] & 3) << 2 | (p[3] & 3))
+            .collect();
+        if bytes.len() < 4 {
+            return Err(ProcessorError::InvalidCartridge("no payload".to_string()));
+        }
+
+        let size = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+        let json = bytes
+            .get(4..4 + size)
+            .ok_or(ProcessorError::InvalidCartridge(
+                "truncated payload".to_string(),
+            ))?;
+        let payload: Payload = serde_json::from_slice(json)
+            .map_err(|err| ProcessorError::InvalidCartridge(err.to_string()))?;
+
+        Ok(Cartridge {
+            name: name.to_string(),
+            source: payload.program,
+            options: payload.options,
+        })
+    }
+
+    pub fn format(&self) -> RomFormat {
+        match self.options.max_size {
+            Some(size) if size > RomFormat::SuperChip.max_size() => RomFormat::XoChip,
+            Some(consts::OCTO_SUPER_CHIP_MAX_SIZE) => RomFormat::SuperChip,
+            _ => RomFormat::Chip8,
+        }
+    }
+
+    pub fn rom(&self) -> Result<Rom, ProcessorError> {
+        let data = octo::assemble(&self.source)?;
+
+        let format = self.format();
+        if data.len() > format.max_size() {
+            return Err(ProcessorError::RomTooLarge(data.len(), format.max_size()));
+        }
+
+        Ok(Rom {
+            name: self.name.clone(),
+            format,
+            data,
+        })
+    }
+
+    pub fn apply(&self, settings: &mut RomSettings) {
+        let options = &self.options;
+
+        settings.platform = self.format().platform().to_string();
+        settings.quirks = Quirks {
+            shift: options.shift_quirks,
+            memory_increment_by_x: false,
+            memory_leave_i_unchanged: options.load_store_quirks,
+            wrap: !options.clip_quirks,
+            jump: options.jump_quirks,
+            vblank: options.v_blank_quirks,
+            logic: options.logic_quirks,
+            // Octo always waits for the key to be released.
+            wait_key_on_press: false,
+        };
+        if let Some(tickrate) = options.tickrate {
+            settings.tickrate = tickrate;
+        }
+
+        let background = options
+            .background_color
+            .as_deref()
+            .and_then(rom_db::parse_color);
+        let foreground = options.fill_color.as_deref().and_then(rom_db::parse_color);
+        if let (Some(background), Some(foreground)) = (background, foreground) {
+            settings.colors = Some([background, foreground]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    fn encode_cartridge(json: &str) -> Vec<u8> {
+        let mut payload = (json.len() as u32).to_be_bytes().to_vec();
+        payload.extend_from_slice(json.as_bytes());
+
+        let mut pixels: Vec<u8> = payload
+            .iter()
+            .flat_map(|byte| [byte >> 6, (byte >> 4) & 3, (byte >> 2) & 3, byte & 3])
+            .collect();
+        let width = 16;
+        pixels.resize(pixels.len().div_ceil(width) * width, 0);
+
+        let mut gif = Vec::new();
+        {
+            let palette = [0, 0, 0, 85, 85, 85, 170, 170, 170, 255, 255, 255];
+            let height = (pixels.len() / width) as u16;
+            let mut encoder = gif::Encoder::new(&mut gif, width as u16, height, &palette).unwrap();
+            let frame = gif::Frame::from_indexed_pixels(width as u16, height, pixels, None);
+            encoder.write_frame(&frame).unwrap();
+        }
+        gif
+    }
+
+    #[test]
+    fn cartridge_test() {
+        let gif = encode_cartridge(
+            r##"{
+                "program": ": main\n0x00 0xE0 # clear\n0x12 0x00\n",
+                "options": {
+                    "tickrate": 20,
+                    "fillColor": "#FFCC00",
+                    "backgroundColor": "#996600",
+                    "clipQuirks": true,
+                    "vBlankQuirks": true
+                }
+            }"##,
+        );
+
+        let cartridge = Cartridge::from_gif("demo", gif.as_slice()).unwrap();
+        let rom = cartridge.rom().unwrap();
+        assert_eq!(rom.data, vec![0x00, 0xE0, 0x12, 0x00]);
+        assert_eq!(rom.format, RomFormat::Chip8);
+
+        let mut settings = rom_db::RomDatabase::from_json("[]", "[]")
+            .unwrap()
+            .lookup(&rom.data, "");
+        cartridge.apply(&mut settings);
+        assert_eq!(settings.tickrate, 20);
+        assert_eq!(
+            settings.colors,
+            Some([[0x99, 0x66, 0x00], [0xff, 0xcc, 0x00]])
+        );
+        assert!(settings.quirks.vblank);
+        assert!(!settings.quirks.wrap);
+    }
+
+    #[test]
+    fn assembler_source_test() {
+        let gif = encode_cartridge(
+            r#"{ "program": ": main\nclear\nloop again", "options": { "maxSize": 3583 } }"#,
+        );
+        let cartridge = Cartridge::from_gif("demo", gif.as_slice()).unwrap();
+        let rom = cartridge.rom().unwrap();
+
+        assert_eq!(rom.data, vec![0x00, 0xE0, 0x12, 0x02]);
+        assert_eq!(rom.format, RomFormat::SuperChip);
+    }
+}
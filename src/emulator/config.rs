@@ -0,0 +1,194 @@
+use crate::emulator::consts;
+use crate::emulator::effects::Effect;
+use crate::emulator::keymap::Keymap;
+use crate::emulator::overlay::Panel;
+use crate::emulator::palette::Palette;
+use crate::emulator::persistence::PersistenceMode;
+use crate::emulator::processor::SelfModifyingCodePolicy;
+use crate::emulator::upscale::Upscaler;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE: &str = "config.json";
+
+// How the window fits the game into its size.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScaleMode {
+    Integer,
+    Fractional,
+}
+
+pub fn config_path(file: &str) -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(base.join(consts::CONFIG_DIR).join(file))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scale_mode: Option<ScaleMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persistence: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upscaler: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshot_dir: Option<PathBuf>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screenshot_metadata: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_panels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keymap: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keys: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub buttons: Option<BTreeMap<String, Vec<String>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub turbo_multiplier: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slow_motion_divisor: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub self_modifying_code: Option<String>,
+}
+
+impl Config {
+    // A missing file is an empty config. One that can't be read or parsed
+    // is an error, so that callers don't save defaults over it.
+    pub fn load() -> io::Result<Config> {
+        match config_path(CONFIG_FILE) {
+            Some(path) => Config::load_from(&path),
+            None => Ok(Config::default()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> io::Result<Config> {
+        let config = match fs::read_to_string(path) {
+            Ok(config) => config,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(err) => return Err(err),
+        };
+
+        serde_json::from_str(&config).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })
+    }
+
+    // Changes a single setting in the config file, leaving the file alone if
+    // it failed to load.
+    pub fn update(change: impl FnOnce(&mut Config)) -> io::Result<()> {
+        let mut config = Config::load()?;
+        change(&mut config);
+
+        config.save()
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path = config_path(CONFIG_FILE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no config directory"))?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+
+    pub fn persistence(&self) -> Option<PersistenceMode> {
+        PersistenceMode::parse(self.persistence.as_deref()?)
+    }
+
+    pub fn effect(&self) -> Option<Effect> {
+        Effect::parse(self.effect.as_deref()?)
+    }
+
+    pub fn upscaler(&self) -> Option<Upscaler> {
+        Upscaler::parse(self.upscaler.as_deref()?)
+    }
+
+    pub fn self_modifying_code(&self) -> Option<SelfModifyingCodePolicy> {
+        SelfModifyingCodePolicy::parse(self.self_modifying_code.as_deref()?)
+    }
+
+    // Unknown panel names are skipped.
+    pub fn debug_panels(&self) -> Option<Vec<Panel>> {
+        let panels = self.debug_panels.as_ref()?;
+        Some(
+            panels
+                .iter()
+                .filter_map(|name| Panel::parse(name))
+                .collect(),
+        )
+    }
+
+    // The keymap profile, QWERTY if unset or unknown, with the keys from
+    // `keys` and controller buttons from `buttons` rebound.
+    pub fn keymap(&self) -> Keymap {
+        let mut keymap = self
+            .keymap
+            .as_deref()
+            .and_then(Keymap::profile)
+            .unwrap_or_default();
+        if let Some(keys) = &self.keys {
+            keymap.rebind(keys);
+        }
+        if let Some(buttons) = &self.buttons {
+            keymap.rebind_buttons(buttons);
+        }
+
+        keymap
+    }
+
+    // An explicit palette wins over a named theme.
+    pub fn palette(&self) -> Option<Palette> {
+        match (&self.palette, &self.theme) {
+            (Some(colors), _) => Palette::parse(&colors.join(",")),
+            (None, Some(theme)) => Palette::theme(theme),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn load_test() {
+        let dir = env::temp_dir().join(format!("chip8-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(CONFIG_FILE);
+
+        assert!(Config::load_from(&path).unwrap().theme.is_none());
+        fs::write(&path, r#"{ "theme": "amber" }"#).unwrap();
+        assert_eq!(
+            Config::load_from(&path).unwrap().theme.as_deref(),
+            Some("amber")
+        );
+        fs::write(&path, r#"{ "theme": "#).unwrap();
+        assert_eq!(
+            Config::load_from(&path).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
 pub const MEMORY_SIZE: usize = 4096;
+pub const XO_MEMORY_SIZE: usize = 0x10000;
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
 pub const DISPLAY_SCALE: usize = 20;
+pub const EFFECT_SCALE: usize = 6;
 pub const ROM_START: usize = 0x200;
 pub const N_REGISTERS: usize = 16;
 pub const N_KEYS: usize = 16;
@@ -10,6 +12,23 @@ pub const DISPLAY_TITLE: &str = "Chip-8 Emulator";
 pub const FONTSET_START: usize = 0x50;
 pub const FONTSET_END: usize = 0xA0;
 pub const KEYPAD_SIZE: usize = 16;
+pub const FRAME_RATE: u32 = 60;
+pub const DEFAULT_PLATFORM: &str = "modernChip8";
+pub const DEFAULT_TICKRATE: usize = 12;
+// The "maxSize" Octo saves with cartridges targeting SUPER-CHIP.
+pub const OCTO_SUPER_CHIP_MAX_SIZE: usize = 3583;
+pub const CONFIG_DIR: &str = "chip8-emu";
+pub const AUDIO_SAMPLE_RATE: u32 = 44100;
+pub const AUDIO_FRAME_SAMPLES: usize = (AUDIO_SAMPLE_RATE / FRAME_RATE) as usize;
+pub const BEEP_FREQUENCY: u32 = 440;
+pub const BEEP_VOLUME: i16 = 8000;
+// A multiple of every upscaler's factor and of EFFECT_SCALE, so that every
+// filtered frame fills a recording by whole pixels.
+pub const RECORDING_SCALE: usize = 6;
+pub const DEFAULT_TURBO_MULTIPLIER: u32 = 4;
+pub const DEFAULT_SLOW_MOTION_DIVISOR: u32 = 4;
+// Stick deflection, out of 32767, below which the stick counts as centred.
+pub const STICK_DEADZONE: i16 = 12000;
 
 pub const FONTSET: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
@@ -0,0 +1,239 @@
+use crate::emulator::consts;
+use crate::emulator::memory_view;
+use crate::emulator::processor::Processor;
+use crate::emulator::terminal::register_panel;
+use crate::emulator::Emulator;
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+// Frames `continue` runs at most before handing the prompt back.
+const CONTINUE_FRAMES: u64 = 60 * consts::FRAME_RATE as u64;
+const DUMP_BYTES: usize = 32;
+
+const HELP: [&str; 10] = [
+    "step [N]          run N instructions (s)",
+    "frame [N]         run N frames (f)",
+    "continue          run to a breakpoint, for at most a minute of frames (c)",
+    "break [ADDR]      toggle a breakpoint, or list them (b)",
+    "regs              show registers, timers and stack (r)",
+    "mem ADDR [LEN]    dump memory as hex, ASCII and sprite rows (m)",
+    "poke ADDR BYTE..  write bytes, like the overlay's hex editor (p)",
+    "key K [up]        press or release CHIP-8 key K (k)",
+    "help              this list (h)",
+    "quit              leave (q)",
+];
+
+// A line-based debugger on an emulator, for consoles and scripts. Addresses
+// and bytes are hexadecimal, counts decimal.
+pub struct Debugger {
+    emulator: Emulator,
+    breakpoints: BTreeSet<u16>,
+}
+
+impl Debugger {
+    pub fn new(emulator: Emulator) -> Debugger {
+        Debugger {
+            emulator,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emulator
+    }
+
+    // Reads commands until `quit` or the end of the input.
+    pub fn run<R: BufRead, W: Write>(&mut self, input: R, mut output: W) -> io::Result<()> {
+        writeln!(output, "{}", self.location())?;
+        write!(output, "> ")?;
+        output.flush()?;
+
+        for line in input.lines() {
+            let line = line?;
+            if matches!(line.trim(), "q" | "quit") {
+                break;
+            }
+            for text in self.execute(&line).unwrap_or_else(|err| vec![err]) {
+                writeln!(output, "{}", text)?;
+            }
+            write!(output, "> ")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    // Runs one command and returns what it prints.
+    pub fn execute(&mut self, command: &str) -> Result<Vec<String>, String> {
+        let mut words = command.split_whitespace();
+        let Some(name) = words.next() else {
+            return Ok(Vec::new());
+        };
+        let args: Vec<&str> = words.collect();
+
+        match name {
+            "s" | "step" => {
+                for _ in 0..count(args.first())? {
+                    if let Some(outcome) = self.emulator.step() {
+                        return Ok(vec![outcome.to_string(), self.location()]);
+                    }
+                }
+                Ok(vec![self.location()])
+            }
+            "f" | "frame" => {
+                for _ in 0..count(args.first())? {
+                    if let Some(outcome) = self.emulator.run_frame() {
+                        return Ok(vec![outcome.to_string(), self.location()]);
+                    }
+                }
+                Ok(vec![self.location()])
+            }
+            "c" | "continue" => {
+                // Leave a breakpoint at PC first.
+                if let Some(outcome) = self.emulator.step() {
+                    return Ok(vec![outcome.to_string(), self.location()]);
+                }
+                let limit = self.emulator.frame_count() + CONTINUE_FRAMES;
+                let breakpoints = &self.breakpoints;
+                let outcome = self.emulator.run_until(|emulator| {
+                    breakpoints.contains(&emulator.pc()) || emulator.frame_count() >= limit
+                });
+
+                let status = match outcome {
+                    Some(outcome) => outcome.to_string(),
+                    None if self.breakpoints.contains(&self.emulator.pc()) => {
+                        "Breakpoint".to_string()
+                    }
+                    None => format!("No breakpoint within {} frames", CONTINUE_FRAMES),
+                };
+                Ok(vec![status, self.location()])
+            }
+            "b" | "break" => {
+                let Some(addr) = args.first() else {
+                    return Ok(self
+                        .breakpoints
+                        .iter()
+                        .map(|addr| format!("{:04X}", addr))
+                        .collect());
+                };
+                let addr = address(addr)?;
+                if self.breakpoints.remove(&addr) {
+                    Ok(vec![format!("Breakpoint at {:04X} removed", addr)])
+                } else {
+                    self.breakpoints.insert(addr);
+                    Ok(vec![format!("Breakpoint at {:04X}", addr)])
+                }
+            }
+            "r" | "regs" => Ok(register_panel(self.emulator.processor())),
+            "m" | "mem" => {
+                let addr = address(args.first().ok_or("mem needs an address")?)?;
+                let len = match args.get(1) {
+                    Some(len) => len.parse().map_err(|_| format!("bad length '{}'", len))?,
+                    None => DUMP_BYTES,
+                };
+                Ok(memory_view::dump(self.emulator.processor(), addr, len))
+            }
+            "p" | "poke" => {
+                let (addr, bytes) = args.split_first().ok_or("poke needs an address")?;
+                let addr = address(addr)?;
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| {
+                        u8::from_str_radix(byte, 16).map_err(|_| format!("bad byte '{}'", byte))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let processor = self.emulator.processor_mut();
+                for (offset, byte) in bytes.iter().enumerate() {
+                    processor.poke(addr.wrapping_add(offset as u16), *byte);
+                }
+                Ok(memory_view::dump(
+                    self.emulator.processor(),
+                    addr,
+                    bytes.len(),
+                ))
+            }
+            "k" | "key" => {
+                let key = args.first().ok_or("key needs a key")?;
+                let key =
+                    usize::from_str_radix(key, 16).map_err(|_| format!("bad key '{}'", key))?;
+                let pressed = args.get(1) != Some(&"up");
+                self.emulator
+                    .set_key(key, pressed)
+                    .map_err(|err| err.to_string())?;
+                Ok(Vec::new())
+            }
+            "h" | "help" => Ok(HELP.iter().map(|line| line.to_string()).collect()),
+            _ => Err(format!("unknown command '{}', try help", name)),
+        }
+    }
+
+    // PC and the instruction there.
+    fn location(&self) -> String {
+        let memory = self.emulator.memory();
+        let pc = self.emulator.pc() as usize;
+        let opcode = u16::from_be_bytes([memory[pc], memory[(pc + 1) % memory.len()]]);
+
+        format!(
+            "{:04X} {:04X} {}",
+            pc,
+            opcode,
+            Processor::decode_instruction(opcode)
+        )
+    }
+}
+
+fn address(word: &str) -> Result<u16, String> {
+    let digits = word.strip_prefix("0x").unwrap_or(word);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("bad address '{}'", word))
+}
+
+fn count(word: Option<&&str>) -> Result<usize, String> {
+    match word {
+        Some(word) => word.parse().map_err(|_| format!("bad count '{}'", word)),
+        None => Ok(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn debugger_test() {
+        // LD V0, 5; ADD V0, 1; JP 202
+        let rom = [0x60, 0x05, 0x70, 0x01, 0x12, 0x02];
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes("test", &rom).unwrap();
+        let mut debugger = Debugger::new(emulator);
+
+        assert_eq!(
+            debugger.execute("step").unwrap(),
+            vec!["0202 7001 ADD V0, 01"]
+        );
+        debugger.execute("break 204").unwrap();
+        assert_eq!(debugger.execute("c").unwrap()[0], "Breakpoint");
+        assert_eq!(debugger.emulator().registers()[0], 6);
+        assert_eq!(debugger.execute("continue").unwrap()[0], "Breakpoint");
+        assert_eq!(debugger.emulator().registers()[0], 7);
+        assert!(debugger.execute("regs").unwrap()[2].starts_with("V0 07"));
+
+        assert_eq!(
+            debugger.execute("poke 300 F0 90").unwrap(),
+            vec!["0300  F0 90        ..    ####.... #..#...."]
+        );
+        // Pokes are tracked like the program's own writes.
+        let processor = debugger.emulator().processor();
+        assert!(processor.last_write(0x301).is_some());
+        assert!(processor.self_modifying_code_report().is_empty());
+
+        let mut output = Vec::new();
+        debugger
+            .run("mem 300 2\nbogus\nquit\nstep\n".as_bytes(), &mut output)
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("0300  F0 90"));
+        assert!(output.contains("unknown command 'bogus'"));
+        assert_eq!(debugger.emulator().pc(), 0x204);
+    }
+}
@@ -1,70 +1,238 @@
+use crate::emulator::config::ScaleMode;
 use crate::emulator::consts;
+use crate::emulator::frame::Frame;
+use crate::emulator::pipeline::Pipeline;
+use crate::emulator::platform::{Platform, PlatformError};
 use sdl2;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
 use sdl2::render;
 use sdl2::video;
 
 pub struct Display {
     window: render::Canvas<video::Window>,
+    // A native 64x32 streaming texture that SDL scales to the window. It is
+    // only replaced while an upscaler or effect changes the frame's size.
+    texture: render::Texture,
+    pipeline: Pipeline,
+    scale_mode: ScaleMode,
+    stale: bool,
+    // The last frame uploaded, after every filter.
+    frame: Frame,
+    // Debug panels shown to the right of the game.
+    overlay: Option<Frame>,
+    overlay_texture: Option<render::Texture>,
 }
 
 impl Display {
-    pub fn new() -> Display {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
-
-        let window = video_subsystem
+    pub fn new(platform: &Platform) -> Result<Display, PlatformError> {
+        let window = platform
+            .video()?
             .window(
                 consts::DISPLAY_TITLE,
                 (consts::DISPLAY_WIDTH * consts::DISPLAY_SCALE) as u32,
                 (consts::DISPLAY_HEIGHT * consts::DISPLAY_SCALE) as u32,
             )
             .position_centered()
+            .resizable()
             .opengl()
             .build()
-            .unwrap();
+            .map_err(|err| PlatformError::Window(err.to_string()))?;
+
+        let canvas = window
+            .into_canvas()
+            .build()
+            .map_err(|err| PlatformError::Window(err.to_string()))?;
 
-        let canvas = window.into_canvas().build().unwrap();
+        // The native framebuffer is stretched by SDL, keep it blocky.
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        let texture = create_texture(&canvas, consts::DISPLAY_WIDTH, consts::DISPLAY_HEIGHT)
+            .map_err(|err| PlatformError::Window(err.to_string()))?;
 
-        Display { window: canvas }
+        Ok(Display {
+            window: canvas,
+            texture,
+            pipeline: Pipeline::new(),
+            scale_mode: ScaleMode::Integer,
+            stale: true,
+            frame: Frame::new(consts::DISPLAY_WIDTH, consts::DISPLAY_HEIGHT),
+            overlay: None,
+            overlay_texture: None,
+        })
     }
 
-    pub fn draw_texture(&mut self, pixels: &[u8; consts::DISPLAY_WIDTH * consts::DISPLAY_HEIGHT]) {
-        let texture_creator = self.window.texture_creator();
+    // Changing the pipeline redraws the frame.
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        self.stale = true;
+        &mut self.pipeline
+    }
 
-        let mut texture = texture_creator
-            .create_texture_streaming(
-                sdl2::pixels::PixelFormatEnum::RGB24,
-                consts::DISPLAY_WIDTH as u32 * consts::DISPLAY_SCALE as u32,
-                consts::DISPLAY_HEIGHT as u32 * consts::DISPLAY_SCALE as u32,
-            )
-            .unwrap();
+    pub fn set_scale_mode(&mut self, scale_mode: ScaleMode) {
+        self.scale_mode = scale_mode;
+        self.stale = true;
+    }
+
+    pub fn scale_mode(&self) -> ScaleMode {
+        self.scale_mode
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let fullscreen = match self.window.window().fullscreen_state() {
+            video::FullscreenType::Off => video::FullscreenType::Desktop,
+            _ => video::FullscreenType::Off,
+        };
+        self.window.window_mut().set_fullscreen(fullscreen)?;
+        self.stale = true;
+
+        Ok(())
+    }
+
+    // Called when the window was resized or exposed.
+    pub fn invalidate(&mut self) {
+        self.stale = true;
+    }
+
+    pub fn set_overlay(&mut self, overlay: Option<Frame>) {
+        self.overlay = overlay;
+        self.stale = true;
+    }
+
+    // Only uploads the framebuffer when the processor changed it, the palette
+    // was switched or a persistence filter is still blending earlier frames.
+    pub fn draw_texture(
+        &mut self,
+        pixels: &[u8; consts::DISPLAY_WIDTH * consts::DISPLAY_HEIGHT],
+        changed: bool,
+    ) {
+        if !changed && !self.stale && !self.pipeline.is_active() {
+            return;
+        }
+        self.stale = false;
 
-        texture
-            .with_lock(None, |buffer: &mut [u8], _| {
-                for i in 0..consts::DISPLAY_HEIGHT {
-                    for j in 0..consts::DISPLAY_WIDTH {
-                        let offset = i * consts::DISPLAY_WIDTH + j;
-                        let color = if pixels[offset] == 0 { 0 } else { 255 };
-
-                        for y in 0..consts::DISPLAY_SCALE {
-                            for x in 0..consts::DISPLAY_SCALE {
-                                let idx = ((i * consts::DISPLAY_SCALE + y)
-                                    * (consts::DISPLAY_WIDTH * consts::DISPLAY_SCALE)
-                                    + (j * consts::DISPLAY_SCALE + x))
-                                    * 3;
-
-                                buffer[idx..idx + 3].fill(color);
-                            }
-                        }
-                    }
+        let frame = self.pipeline.apply(pixels);
+        upload(&self.window, &mut self.texture, &frame);
+        if let Some(overlay) = &self.overlay {
+            match &mut self.overlay_texture {
+                Some(texture) => upload(&self.window, texture, overlay),
+                None => {
+                    let mut texture =
+                        create_texture(&self.window, overlay.width, overlay.height).unwrap();
+                    upload(&self.window, &mut texture, overlay);
+                    self.overlay_texture = Some(texture);
                 }
-            })
-            .unwrap();
+            }
+        }
 
+        let (game_viewport, overlay_viewport) = self.layout();
+        self.window.set_draw_color(Color::BLACK);
         self.window.clear();
-        self.window.copy(&texture, None, None).unwrap();
+        self.window
+            .copy(&self.texture, None, game_viewport)
+            .unwrap();
+        if let (Some(texture), Some(viewport)) = (&self.overlay_texture, overlay_viewport) {
+            self.window.copy(texture, None, viewport).unwrap();
+        }
         self.window.present();
+        self.frame = frame;
     }
+
+    // The game gets whatever space the overlay leaves on the left.
+    fn layout(&self) -> (Rect, Option<Rect>) {
+        let (width, height) = self.window.output_size().unwrap();
+        let Some(overlay) = &self.overlay else {
+            return (viewport((width, height), self.scale_mode), None);
+        };
+
+        // Never more than half of the window.
+        let scale = f32::min(
+            height as f32 / overlay.height as f32,
+            (width / 2) as f32 / overlay.width as f32,
+        );
+        let scale = if scale >= 1.0 { scale.floor() } else { scale };
+        let overlay_width = (overlay.width as f32 * scale) as u32;
+        let overlay_viewport = Rect::new(
+            (width - overlay_width) as i32,
+            0,
+            overlay_width,
+            (overlay.height as f32 * scale) as u32,
+        );
+
+        (
+            viewport((width - overlay_width, height), self.scale_mode),
+            Some(overlay_viewport),
+        )
+    }
+
+    // The last drawn frame, enlarged by the largest whole factor that still
+    // fits the viewport so it looks like the window contents.
+    pub fn screenshot(&self) -> Frame {
+        let (viewport, _) = self.layout();
+        self.frame
+            .scale((viewport.width() as usize / self.frame.width).max(1))
+    }
+}
+
+fn create_texture(
+    canvas: &render::Canvas<video::Window>,
+    width: usize,
+    height: usize,
+) -> Result<render::Texture, render::TextureValueError> {
+    canvas.texture_creator().create_texture_streaming(
+        sdl2::pixels::PixelFormatEnum::RGB24,
+        width as u32,
+        height as u32,
+    )
+}
+
+// Copies `frame` into a streaming texture. The texture is kept while the size
+// stays the same, which it does unless an upscaler, effect or the overlay's
+// panels change it.
+fn upload(canvas: &render::Canvas<video::Window>, texture: &mut render::Texture, frame: &Frame) {
+    let query = texture.query();
+    if (query.width as usize, query.height as usize) != (frame.width, frame.height) {
+        let new_texture = create_texture(canvas, frame.width, frame.height).unwrap();
+        let old = std::mem::replace(texture, new_texture);
+        // SAFETY: with `unsafe_textures` a texture isn't tied to the lifetime
+        // of its renderer, and destroying one is only sound while that
+        // renderer is still alive and the texture is never used again. `old`
+        // was created from `canvas`, which the caller borrows and so is alive
+        // for this call. It was moved out of `texture`, so nothing else can
+        // reach it afterwards.
+        unsafe { old.destroy() };
+    }
+
+    texture
+        .with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            for y in 0..frame.height {
+                for x in 0..frame.width {
+                    let idx = y * pitch + x * 3;
+                    buffer[idx..idx + 3].copy_from_slice(&frame.get(x, y));
+                }
+            }
+        })
+        .unwrap();
+}
+
+// Largest rectangle with the framebuffer's aspect ratio that fits the window,
+// centered so the remaining space is letterboxed.
+pub fn viewport((width, height): (u32, u32), scale_mode: ScaleMode) -> Rect {
+    let scale = f32::min(
+        width as f32 / consts::DISPLAY_WIDTH as f32,
+        height as f32 / consts::DISPLAY_HEIGHT as f32,
+    );
+    let scale = match scale_mode {
+        ScaleMode::Integer => scale.floor().max(1.0),
+        ScaleMode::Fractional => scale,
+    };
+
+    let viewport_width = (consts::DISPLAY_WIDTH as f32 * scale) as u32;
+    let viewport_height = (consts::DISPLAY_HEIGHT as f32 * scale) as u32;
+    Rect::new(
+        (width as i32 - viewport_width as i32) / 2,
+        (height as i32 - viewport_height as i32) / 2,
+        viewport_width,
+        viewport_height,
+    )
 }
 
 #[cfg(test)]
@@ -72,10 +240,27 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn test_viewport() {
+        assert_eq!(
+            viewport((1280, 640), ScaleMode::Integer),
+            Rect::new(0, 0, 1280, 640)
+        );
+        assert_eq!(
+            viewport((1000, 1000), ScaleMode::Integer),
+            Rect::new(20, 260, 960, 480)
+        );
+        assert_eq!(
+            viewport((1000, 1000), ScaleMode::Fractional),
+            Rect::new(0, 250, 1000, 500)
+        );
+    }
+
     #[test]
     fn test_display() {
         let pixels = [1; consts::DISPLAY_HEIGHT * consts::DISPLAY_WIDTH];
-        let mut display = Display::new();
+        let platform = Platform::new().unwrap();
+        let mut display = Display::new(&platform).unwrap();
         assert_eq!(display.window.window().title(), consts::DISPLAY_TITLE);
         assert_eq!(
             display.window.window().size(),
@@ -85,7 +270,7 @@ mod tests {
             )
         );
 
-        display.draw_texture(&pixels);
+        display.draw_texture(&pixels, true);
         loop {}
     }
 }
@@ -0,0 +1,210 @@
+use crate::emulator::consts;
+use crate::emulator::frame::Frame;
+
+pub const EFFECTS: [&str; 6] = ["none", "scanlines", "grid", "bloom", "curvature", "crt"];
+
+const SCANLINE_BRIGHTNESS: f32 = 0.5;
+const GRID_BRIGHTNESS: f32 = 0.6;
+const BLOOM_STRENGTH: f32 = 0.6;
+const CURVATURE: f32 = 0.08;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Effect {
+    None,
+    Scanlines,
+    Grid,
+    Bloom,
+    Curvature,
+    // Scanlines, bloom and curvature together.
+    Crt,
+}
+
+impl Effect {
+    pub fn parse(name: &str) -> Option<Effect> {
+        match name {
+            "none" => Some(Effect::None),
+            "scanlines" => Some(Effect::Scanlines),
+            "grid" => Some(Effect::Grid),
+            "bloom" => Some(Effect::Bloom),
+            "curvature" => Some(Effect::Curvature),
+            "crt" => Some(Effect::Crt),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Effect::None => "none",
+            Effect::Scanlines => "scanlines",
+            Effect::Grid => "grid",
+            Effect::Bloom => "bloom",
+            Effect::Curvature => "curvature",
+            Effect::Crt => "crt",
+        }
+    }
+
+    pub fn next(&self) -> Effect {
+        let current = EFFECTS
+            .iter()
+            .position(|name| *name == self.name())
+            .unwrap();
+        Effect::parse(EFFECTS[(current + 1) % EFFECTS.len()]).unwrap()
+    }
+
+    // Effects need several output pixels per CHIP-8 pixel, so the frame is
+    // enlarged to about EFFECT_SCALE of them first. An upscaled frame already
+    // has some, and scanlines and the grid follow whole CHIP-8 pixels either
+    // way.
+    pub fn apply(&self, frame: &Frame) -> Frame {
+        if *self == Effect::None {
+            return frame.clone();
+        }
+
+        let upscaled = (frame.width / consts::DISPLAY_WIDTH).max(1);
+        let scale = (consts::EFFECT_SCALE / upscaled).max(1);
+        let cell = upscaled * scale;
+        let frame = frame.scale(scale);
+
+        match self {
+            Effect::None => frame,
+            Effect::Scanlines => scanlines(&frame, cell),
+            Effect::Grid => grid(&frame, cell),
+            Effect::Bloom => bloom(&frame, cell),
+            Effect::Curvature => curvature(&frame),
+            Effect::Crt => curvature(&bloom(&scanlines(&frame, cell), cell)),
+        }
+    }
+}
+
+fn darken(color: [u8; 3], factor: f32) -> [u8; 3] {
+    color.map(|channel| (channel as f32 * factor) as u8)
+}
+
+// `cell` is the size of a CHIP-8 pixel in the frame.
+fn scanlines(frame: &Frame, cell: usize) -> Frame {
+    let mut output = frame.clone();
+    let thickness = (cell / 3).max(1);
+
+    for y in (0..frame.height).filter(|y| y % cell >= cell - thickness) {
+        for x in 0..frame.width {
+            output.set(x, y, darken(frame.get(x, y), SCANLINE_BRIGHTNESS));
+        }
+    }
+
+    output
+}
+
+fn grid(frame: &Frame, cell: usize) -> Frame {
+    let mut output = frame.clone();
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            if x % cell == cell - 1 || y % cell == cell - 1 {
+                output.set(x, y, darken(frame.get(x, y), GRID_BRIGHTNESS));
+            }
+        }
+    }
+
+    output
+}
+
+// A separable box blur of the frame added back on top of it.
+fn bloom(frame: &Frame, radius: usize) -> Frame {
+    let blur = |frame: &Frame, horizontal: bool| {
+        let mut output = Frame::new(frame.width, frame.height);
+        for y in 0..frame.height {
+            for x in 0..frame.width {
+                let (position, size) = if horizontal {
+                    (x, frame.width)
+                } else {
+                    (y, frame.height)
+                };
+                let start = position.saturating_sub(radius);
+                let end = (position + radius).min(size - 1);
+
+                let mut sum = [0u32; 3];
+                for i in start..=end {
+                    let color = if horizontal {
+                        frame.get(i, y)
+                    } else {
+                        frame.get(x, i)
+                    };
+                    for channel in 0..3 {
+                        sum[channel] += color[channel] as u32;
+                    }
+                }
+                let count = (end - start + 1) as u32;
+                output.set(x, y, sum.map(|channel| (channel / count) as u8));
+            }
+        }
+        output
+    };
+
+    let glow = blur(&blur(frame, true), false);
+    let mut output = frame.clone();
+    for (pixel, glow) in output.pixels.iter_mut().zip(&glow.pixels) {
+        for channel in 0..3 {
+            let value = pixel[channel] as f32 + glow[channel] as f32 * BLOOM_STRENGTH;
+            pixel[channel] = value.min(255.0) as u8;
+        }
+    }
+
+    output
+}
+
+// Barrel distortion: every output pixel samples the source further out the
+// closer it is to the edges, and corners that fall outside turn black.
+fn curvature(frame: &Frame) -> Frame {
+    let mut output = Frame::new(frame.width, frame.height);
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let u = 2.0 * x as f32 / (frame.width - 1) as f32 - 1.0;
+            let v = 2.0 * y as f32 / (frame.height - 1) as f32 - 1.0;
+            let source_u = u * (1.0 + CURVATURE * v * v);
+            let source_v = v * (1.0 + CURVATURE * u * u);
+
+            if source_u.abs() <= 1.0 && source_v.abs() <= 1.0 {
+                let source_x = ((source_u + 1.0) / 2.0 * (frame.width - 1) as f32).round();
+                let source_y = ((source_v + 1.0) / 2.0 * (frame.height - 1) as f32).round();
+                output.set(x, y, frame.get(source_x as usize, source_y as usize));
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn effects_test() {
+        let mut frame = Frame::new(consts::DISPLAY_WIDTH, consts::DISPLAY_HEIGHT);
+        frame.pixels.fill([255; 3]);
+
+        for name in EFFECTS {
+            let output = Effect::parse(name).unwrap().apply(&frame);
+            assert_eq!(Effect::parse(name).unwrap().name(), name);
+            if name != "none" {
+                assert_eq!(output.width, consts::DISPLAY_WIDTH * consts::EFFECT_SCALE);
+            }
+        }
+
+        let scanlines = Effect::Scanlines.apply(&frame);
+        assert_eq!(scanlines.get(0, 0), [255; 3]);
+        assert_eq!(scanlines.get(0, consts::EFFECT_SCALE - 1), [127; 3]);
+
+        // After a 2x upscaler the lines still fall on every CHIP-8 pixel.
+        let upscaled = Effect::Scanlines.apply(&frame.scale(2));
+        assert_eq!(upscaled.width, consts::DISPLAY_WIDTH * consts::EFFECT_SCALE);
+        assert_eq!(upscaled.get(0, 2), [255; 3]);
+        assert_eq!(upscaled.get(0, consts::EFFECT_SCALE - 1), [127; 3]);
+
+        let curved = Effect::Curvature.apply(&frame);
+        assert_eq!(curved.get(curved.width / 2, curved.height / 2), [255; 3]);
+        assert_eq!(curved.get(0, 0), [0; 3]);
+    }
+}
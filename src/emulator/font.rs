@@ -0,0 +1,55 @@
+use crate::emulator::consts;
+
+pub const GLYPH_WIDTH: usize = 4;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// Glyphs for everything but the hex digits, which come from FONTSET. Rows
+// use the high nibble like the CHIP-8 font does.
+const GLYPHS: [(char, [u8; GLYPH_HEIGHT]); 29] = [
+    ('G', [0xF0, 0x80, 0xB0, 0x90, 0xF0]),
+    ('H', [0x90, 0x90, 0xF0, 0x90, 0x90]),
+    ('I', [0xE0, 0x40, 0x40, 0x40, 0xE0]),
+    ('J', [0x10, 0x10, 0x10, 0x90, 0x60]),
+    ('K', [0x90, 0xA0, 0xC0, 0xA0, 0x90]),
+    ('L', [0x80, 0x80, 0x80, 0x80, 0xF0]),
+    ('M', [0x90, 0xF0, 0xF0, 0x90, 0x90]),
+    ('N', [0x90, 0xD0, 0xB0, 0x90, 0x90]),
+    ('O', [0x60, 0x90, 0x90, 0x90, 0x60]),
+    ('P', [0xE0, 0x90, 0xE0, 0x80, 0x80]),
+    ('Q', [0x60, 0x90, 0x90, 0xB0, 0x70]),
+    ('R', [0xE0, 0x90, 0xE0, 0xA0, 0x90]),
+    ('S', [0x70, 0x80, 0x60, 0x10, 0xE0]),
+    ('T', [0xE0, 0x40, 0x40, 0x40, 0x40]),
+    ('U', [0x90, 0x90, 0x90, 0x90, 0x60]),
+    ('V', [0x90, 0x90, 0xA0, 0xA0, 0x40]),
+    ('W', [0x90, 0x90, 0xF0, 0xF0, 0x90]),
+    ('X', [0x90, 0x90, 0x60, 0x90, 0x90]),
+    ('Y', [0xA0, 0xA0, 0x40, 0x40, 0x40]),
+    ('Z', [0xF0, 0x10, 0x60, 0x80, 0xF0]),
+    (':', [0x00, 0x40, 0x00, 0x40, 0x00]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x40]),
+    (',', [0x00, 0x00, 0x00, 0x40, 0x80]),
+    ('-', [0x00, 0x00, 0xE0, 0x00, 0x00]),
+    ('>', [0x80, 0x40, 0x20, 0x40, 0x80]),
+    ('/', [0x10, 0x10, 0x20, 0x40, 0x80]),
+    ('=', [0x00, 0xE0, 0x00, 0xE0, 0x00]),
+    ('[', [0x60, 0x40, 0x40, 0x40, 0x60]),
+    (']', [0x60, 0x20, 0x20, 0x20, 0x60]),
+];
+
+// Rows of the glyph for `c`, case-insensitive. Unknown characters are blank.
+pub fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    let c = c.to_ascii_uppercase();
+    if let Some(digit) = c.to_digit(16) {
+        let start = digit as usize * GLYPH_HEIGHT;
+        return consts::FONTSET[start..start + GLYPH_HEIGHT]
+            .try_into()
+            .unwrap();
+    }
+
+    GLYPHS
+        .iter()
+        .find(|(glyph_char, _)| *glyph_char == c)
+        .map(|(_, rows)| *rows)
+        .unwrap_or([0; GLYPH_HEIGHT])
+}
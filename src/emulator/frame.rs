@@ -0,0 +1,67 @@
+use crate::emulator::palette::Palette;
+
+// An RGB image, the common currency of the post-processing and export code.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: vec![[0; 3]; width * height],
+        }
+    }
+
+    pub fn from_indices(indices: &[u8], width: usize, height: usize, palette: &Palette) -> Frame {
+        Frame {
+            width,
+            height,
+            pixels: indices.iter().map(|index| palette.color(*index)).collect(),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> [u8; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    pub fn scale(&self, factor: usize) -> Frame {
+        let mut scaled = Frame::new(self.width * factor, self.height * factor);
+        for y in 0..scaled.height {
+            for x in 0..scaled.width {
+                scaled.set(x, y, self.get(x / factor, y / factor));
+            }
+        }
+
+        scaled
+    }
+
+    // Nearest-neighbour resampling, the same as `scale` when the sizes are
+    // whole multiples.
+    pub fn resize(&self, width: usize, height: usize) -> Frame {
+        let mut resized = Frame::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                resized.set(
+                    x,
+                    y,
+                    self.get(x * self.width / width, y * self.height / height),
+                );
+            }
+        }
+
+        resized
+    }
+
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels.concat()
+    }
+}
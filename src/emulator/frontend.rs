@@ -0,0 +1,306 @@
+use crate::emulator::{
+    config, consts, display, input, overlay, platform, screenshot, Emulator, RunOutcome,
+};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+// The window and input of an emulator that has them.
+pub(super) struct Frontend {
+    platform: platform::Platform,
+    pub(super) display: display::Display,
+    pub(super) input: input::Input,
+    // The CHIP-8 key waiting for a controller button.
+    button_mapping: Option<usize>,
+    counters: overlay::Counters,
+    counter_start: Instant,
+    counted_frames: u32,
+}
+
+impl Emulator {
+    // Opens the window and applies the config file.
+    pub fn new() -> Result<Emulator, platform::PlatformError> {
+        let platform = platform::Platform::new()?;
+        let display = display::Display::new(&platform)?;
+        let input = input::Input::new(&platform)?;
+        let mut emulator = Emulator {
+            frontend: Some(Frontend {
+                platform,
+                display,
+                input,
+                button_mapping: None,
+                counters: overlay::Counters::default(),
+                counter_start: Instant::now(),
+                counted_frames: 0,
+            }),
+            ..Emulator::headless()
+        };
+
+        let config = config::Config::load().unwrap_or_else(|err| {
+            println!("{}", err);
+            config::Config::default()
+        });
+        if let Some(palette) = config.palette() {
+            emulator.set_palette(palette);
+        }
+        if let Some(scale_mode) = config.scale_mode {
+            if let Some(display) = emulator.display_mut() {
+                display.set_scale_mode(scale_mode);
+            }
+        }
+        if let Some(mode) = config.persistence() {
+            emulator.set_persistence(mode);
+        }
+        if let Some(effect) = config.effect() {
+            emulator.set_effect(effect);
+        }
+        if let Some(upscaler) = config.upscaler() {
+            emulator.set_upscaler(upscaler);
+        }
+        if let Some(dir) = &config.screenshot_dir {
+            emulator.screenshot_dir = dir.clone();
+        }
+        if let Some(metadata) = config.screenshot_metadata {
+            emulator.screenshot_metadata = metadata;
+        }
+        if let Some(panels) = config.debug_panels() {
+            emulator.overlay.set_panels(panels);
+        }
+        emulator.set_keymap(config.keymap());
+        if let Some(multiplier) = config.turbo_multiplier {
+            emulator.set_turbo_multiplier(multiplier);
+        }
+        if let Some(divisor) = config.slow_motion_divisor {
+            emulator.set_slow_motion_divisor(divisor);
+        }
+        if let Some(policy) = config.self_modifying_code() {
+            emulator.set_self_modifying_code_policy(policy);
+        }
+
+        Ok(emulator)
+    }
+
+    // For subsystems the emulator doesn't use itself, such as audio. None
+    // when headless.
+    pub fn platform(&self) -> Option<&platform::Platform> {
+        self.frontend.as_ref().map(|frontend| &frontend.platform)
+    }
+
+    pub(super) fn display(&self) -> Option<&display::Display> {
+        self.frontend.as_ref().map(|frontend| &frontend.display)
+    }
+
+    pub(super) fn display_mut(&mut self) -> Option<&mut display::Display> {
+        self.frontend.as_mut().map(|frontend| &mut frontend.display)
+    }
+
+    // Draws the frame and handles the input gathered since the last one,
+    // after `frames` emulated frames. Returns the outcome that ends the run,
+    // if any.
+    pub(super) fn present(&mut self, frames: u32) -> Option<RunOutcome> {
+        let frontend = self.frontend.as_mut()?;
+
+        frontend.counted_frames += frames;
+        let elapsed = frontend.counter_start.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            let elapsed = elapsed.as_secs_f32();
+            let frames = frontend.counted_frames as f32;
+            frontend.counters = overlay::Counters {
+                fps: (frames / elapsed).round() as u32,
+                ips: (frames * self.tickrate as f32 / elapsed).round() as u32,
+            };
+            frontend.counter_start = Instant::now();
+            frontend.counted_frames = 0;
+        }
+
+        if self.overlay.is_visible() {
+            let panels = self.overlay.render(&self.processor, frontend.counters);
+            frontend.display.set_overlay(Some(panels));
+        }
+        let changed = self.processor.take_display_changed();
+        frontend
+            .display
+            .draw_texture(&self.processor.display, changed);
+        let actions = frontend.input.handle_keypress(&mut self.processor);
+
+        for action in actions {
+            match action {
+                input::Action::NextTheme => self.next_theme(),
+                input::Action::NextEffect => {
+                    self.set_effect(self.pipeline.effect().next());
+                }
+                input::Action::NextUpscaler => {
+                    self.set_upscaler(self.pipeline.upscaler().next());
+                }
+                input::Action::ToggleScaleMode => self.toggle_scale_mode(),
+                input::Action::ToggleFullscreen => {
+                    if let Some(Err(err)) = self
+                        .display_mut()
+                        .map(|display| display.toggle_fullscreen())
+                    {
+                        println!("{}", err);
+                    }
+                }
+                input::Action::Screenshot { native } => match self.save_screenshot(native) {
+                    Ok(path) => println!("Saved {}", path.display()),
+                    Err(err) => println!("{}", err),
+                },
+                input::Action::SaveSpriteSheet => match self.save_sprite_sheet() {
+                    Ok(path) => println!("Saved {}", path.display()),
+                    Err(err) => println!("{}", err),
+                },
+                input::Action::ToggleRecording { video } => self.toggle_recording(video),
+                input::Action::ToggleOverlay => self.toggle_overlay(),
+                input::Action::TogglePanel(panel) => self.toggle_overlay_panel(panel),
+                input::Action::ScrollMemory(_)
+                | input::Action::ShowPcInMemory
+                | input::Action::ShowIndexInMemory
+                | input::Action::ToggleMemoryEditing
+                | input::Action::MoveMemoryCursor(_)
+                | input::Action::EnterNibble(_) => self.memory_action(action),
+                input::Action::ToggleButtonMapping
+                | input::Action::SkipButtonMapping
+                | input::Action::MapButton(_) => self.map_buttons(action),
+                input::Action::Redraw => {
+                    if let Some(display) = self.display_mut() {
+                        display.invalidate();
+                    }
+                }
+                input::Action::TogglePause => {
+                    self.set_paused(!self.paused);
+                    println!("{}", if self.paused { "Paused" } else { "Resumed" });
+                }
+                input::Action::AdvanceFrame => self.advance_frame(),
+                input::Action::SoftReset => self.soft_reset(),
+                input::Action::HardReset => {
+                    if let Err(err) = self.hard_reset() {
+                        println!("Hard reset failed, the ROM keeps running: {}", err);
+                    }
+                }
+                input::Action::Turbo(turbo) => self.turbo = turbo,
+                input::Action::ToggleSlowMotion => {
+                    self.slow_motion = !self.slow_motion;
+                    self.slow_motion_wait = 0;
+                }
+                input::Action::Quit => return Some(RunOutcome::Quit),
+            }
+        }
+
+        None
+    }
+
+    // Cycles from the theme in use, so `--theme` or the config file's
+    // choice isn't skipped back to the first one.
+    fn next_theme(&mut self) {
+        self.set_palette(self.pipeline.palette().next_theme());
+    }
+
+    fn memory_action(&mut self, action: input::Action) {
+        let view = self.overlay.memory_view_mut();
+        match action {
+            input::Action::ScrollMemory(rows) => view.scroll(rows),
+            input::Action::ShowPcInMemory => view.jump(self.processor.pc()),
+            input::Action::ShowIndexInMemory => view.jump(self.processor.index()),
+            input::Action::ToggleMemoryEditing => self.overlay.toggle_memory_editing(),
+            input::Action::MoveMemoryCursor(offset) => view.move_cursor(offset),
+            input::Action::EnterNibble(nibble) => view.enter_nibble(&mut self.processor, nibble),
+            _ => {}
+        }
+        self.sync_overlay();
+    }
+
+    // Hides the overlay in the window along with the panels, and keeps the
+    // keyboard from editing memory that isn't shown.
+    pub(super) fn sync_overlay(&mut self) {
+        let Some(frontend) = &mut self.frontend else {
+            return;
+        };
+        if !self.overlay.is_visible() {
+            frontend.display.set_overlay(None);
+        }
+        frontend
+            .input
+            .set_memory_editing(self.overlay.memory_view().is_editing());
+    }
+
+    // Walks through the CHIP-8 keys, binding the next controller button
+    // pressed to each. The result is saved to the config file.
+    fn map_buttons(&mut self, action: input::Action) {
+        let Some(button_mapping) = self
+            .frontend
+            .as_ref()
+            .map(|frontend| frontend.button_mapping)
+        else {
+            return;
+        };
+        let next = match (action, button_mapping) {
+            (input::Action::ToggleButtonMapping, None) => {
+                if !self.overlay.is_visible() {
+                    self.toggle_overlay();
+                }
+                Some(0)
+            }
+            (input::Action::SkipButtonMapping, Some(key)) => Some(key + 1),
+            (input::Action::MapButton(button), Some(key)) => {
+                let buttons = BTreeMap::from([(format!("{:X}", key), vec![button.string()])]);
+                self.keymap.rebind_buttons(&buttons);
+                self.active_keymap.rebind_buttons(&buttons);
+                if let Some(frontend) = &mut self.frontend {
+                    frontend.input.set_keymap(&self.active_keymap);
+                }
+                Some(key + 1)
+            }
+            _ => None,
+        }
+        .filter(|key| *key < consts::N_KEYS);
+
+        if button_mapping.is_some() && next.is_none() {
+            let buttons = self.keymap.buttons_by_key();
+            if let Err(err) = config::Config::update(|config| config.buttons = Some(buttons)) {
+                println!("{}", err);
+            }
+        }
+        if let Some(frontend) = &mut self.frontend {
+            frontend.button_mapping = next;
+            frontend.input.set_button_mapping(next.is_some());
+        }
+        self.overlay
+            .set_button_prompt(next.map(|key| overlay::ButtonPrompt {
+                key,
+                buttons: self.active_keymap.buttons_by_key(),
+            }));
+    }
+
+    fn toggle_recording(&mut self, video: bool) {
+        if self.is_recording() {
+            match self.stop_recording() {
+                Ok(()) => println!("Recording stopped"),
+                Err(err) => println!("{}", err),
+            }
+            return;
+        }
+
+        let extension = if video { "avi" } else { "gif" };
+        let path = screenshot::timestamped_path(&self.screenshot_dir, extension);
+        match self.start_recording(&path) {
+            Ok(()) => println!("Recording to {}", path.display()),
+            Err(err) => println!("{}", err),
+        }
+    }
+
+    // The chosen mode is written back to the config file so the next session
+    // starts with it.
+    fn toggle_scale_mode(&mut self) {
+        let Some(display) = self.display_mut() else {
+            return;
+        };
+        let scale_mode = match display.scale_mode() {
+            config::ScaleMode::Integer => config::ScaleMode::Fractional,
+            config::ScaleMode::Fractional => config::ScaleMode::Integer,
+        };
+        display.set_scale_mode(scale_mode);
+
+        if let Err(err) = config::Config::update(|config| config.scale_mode = Some(scale_mode)) {
+            println!("{}", err);
+        }
+    }
+}
@@ -1,82 +1,367 @@
 use crate::emulator::consts;
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use crate::emulator::keymap::Keymap;
+use crate::emulator::overlay::Panel;
+use crate::emulator::platform::{Platform, PlatformError};
+use crate::emulator::processor::Processor;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use std::collections::HashSet;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    NextTheme,
+    NextEffect,
+    NextUpscaler,
+    ToggleScaleMode,
+    ToggleFullscreen,
+    Screenshot { native: bool },
+    SaveSpriteSheet,
+    ToggleOverlay,
+    TogglePanel(Panel),
+    ScrollMemory(i32),
+    ShowPcInMemory,
+    ShowIndexInMemory,
+    ToggleMemoryEditing,
+    MoveMemoryCursor(i32),
+    EnterNibble(u8),
+    ToggleRecording { video: bool },
+    ToggleButtonMapping,
+    SkipButtonMapping,
+    MapButton(Button),
+    TogglePause,
+    AdvanceFrame,
+    SoftReset,
+    HardReset,
+    // Fast-forward while the key is held.
+    Turbo(bool),
+    ToggleSlowMotion,
+    Quit,
+    Redraw,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Host {
+    Key(Keycode),
+    Button(Button),
+}
 
 pub struct Input {
     event_pump: sdl2::EventPump,
+    controller_subsystem: Option<sdl2::GameControllerSubsystem>,
+    controllers: Vec<GameController>,
+    keymap: Vec<(Host, usize)>,
+    // Mapped keys and buttons currently down, with the controller a button
+    // is on, so that releasing one of several bound to the same CHIP-8 key
+    // doesn't release it.
+    held: HashSet<(Option<u32>, Host)>,
+    // D-pad directions each stick of each controller is pushed in.
+    stick: HashSet<(u32, Axis, Button)>,
+    memory_editing: bool,
+    button_mapping: bool,
 }
 
 impl Input {
-    pub fn new() -> Input {
-        let sdl_context = sdl2::init().unwrap();
-        let event_pump = sdl_context.event_pump().unwrap();
-        Input { event_pump }
+    pub fn new(platform: &Platform) -> Result<Input, PlatformError> {
+        let event_pump = platform.event_pump()?;
+        // Controllers already plugged in are reported as added on the first
+        // poll, just like ones plugged in later. Without the subsystem the
+        // keyboard still works.
+        let controller_subsystem = platform.game_controller().ok();
+        let mut input = Input {
+            event_pump,
+            controller_subsystem,
+            controllers: Vec::new(),
+            keymap: Vec::new(),
+            held: HashSet::new(),
+            stick: HashSet::new(),
+            memory_editing: false,
+            button_mapping: false,
+        };
+        input.set_keymap(&Keymap::default());
+
+        Ok(input)
+    }
+
+    // While the memory editor is active, hex digits and arrow keys edit
+    // memory instead of reaching the keypad.
+    pub fn set_memory_editing(&mut self, editing: bool) {
+        self.memory_editing = editing;
+    }
+
+    // While controller buttons are being mapped, button presses are
+    // reported as actions instead of reaching the keypad.
+    pub fn set_button_mapping(&mut self, mapping: bool) {
+        self.button_mapping = mapping;
+    }
+
+    // Key and button names SDL doesn't know are skipped.
+    pub fn set_keymap(&mut self, keymap: &Keymap) {
+        let keys = keymap
+            .bindings()
+            .iter()
+            .filter_map(|(name, key)| Some((Host::Key(Keycode::from_name(name)?), *key)));
+        let buttons = keymap
+            .button_bindings()
+            .iter()
+            .filter_map(|(name, key)| Some((Host::Button(Button::from_string(name)?), *key)));
+        self.keymap = keys.chain(buttons).collect();
+        self.held.clear();
+        self.stick.clear();
+    }
+
+    // `controller` is None for the keyboard.
+    fn set_host(
+        &mut self,
+        controller: Option<u32>,
+        host: Host,
+        pressed: bool,
+        processor: &mut Processor,
+    ) {
+        if pressed {
+            self.held.insert((controller, host));
+        } else {
+            self.held.remove(&(controller, host));
+        }
+        self.refresh(host, processor);
+    }
+
+    // Moves either stick along one axis, pressing or releasing the keys bound
+    // to the D-pad directions it points in.
+    fn set_stick(&mut self, controller: u32, axis: Axis, value: i16, processor: &mut Processor) {
+        let directions = match axis {
+            Axis::LeftX | Axis::RightX => [Button::DPadLeft, Button::DPadRight],
+            Axis::LeftY | Axis::RightY => [Button::DPadUp, Button::DPadDown],
+            _ => return,
+        };
+        let pushed = if value < -consts::STICK_DEADZONE {
+            Some(directions[0])
+        } else if value > consts::STICK_DEADZONE {
+            Some(directions[1])
+        } else {
+            None
+        };
+        for direction in directions {
+            if Some(direction) == pushed {
+                self.stick.insert((controller, axis, direction));
+            } else {
+                self.stick.remove(&(controller, axis, direction));
+            }
+            self.refresh(Host::Button(direction), processor);
+        }
+    }
+
+    fn is_down(&self, host: Host) -> bool {
+        self.held.iter().any(|(_, held)| *held == host)
+            || matches!(host, Host::Button(button)
+                if self.stick.iter().any(|(_, _, pushed)| *pushed == button))
+    }
+
+    // Keys bound to the keypad win over the turbo and slow motion hotkeys.
+    fn is_bound(&self, keycode: Keycode) -> bool {
+        self.keymap
+            .iter()
+            .any(|(bound, _)| *bound == Host::Key(keycode))
+    }
+
+    fn refresh(&self, host: Host, processor: &mut Processor) {
+        let Some(&(_, key)) = self.keymap.iter().find(|(bound, _)| *bound == host) else {
+            return;
+        };
+        let pressed = self
+            .keymap
+            .iter()
+            .any(|(bound, bound_key)| *bound_key == key && self.is_down(*bound));
+        // Bindings come from config files, which may name keys that don't
+        // exist.
+        if let Err(err) = processor.set_key(key, pressed) {
+            println!("{}", err);
+        }
+    }
+
+    fn add_controller(&mut self, joystick_index: u32) {
+        let Some(subsystem) = &self.controller_subsystem else {
+            return;
+        };
+        match subsystem.open(joystick_index) {
+            Ok(controller) => {
+                println!("Controller connected: {}", controller.name());
+                self.controllers.push(controller);
+            }
+            Err(err) => println!("{}", err),
+        }
     }
 
-    pub fn handle_keypress(&mut self, is_key_pressed: &mut [bool; consts::KEYPAD_SIZE]) {
-        for event in self.event_pump.poll_iter() {
+    // Whatever the controller held is released, other controllers keep
+    // theirs.
+    fn remove_controller(&mut self, instance_id: u32, processor: &mut Processor) {
+        self.controllers
+            .retain(|controller| controller.instance_id() != instance_id);
+        self.held
+            .retain(|(controller, _)| *controller != Some(instance_id));
+        self.stick
+            .retain(|(controller, _, _)| *controller != instance_id);
+        let buttons = self
+            .keymap
+            .iter()
+            .map(|(host, _)| *host)
+            .filter(|host| matches!(host, Host::Button(_)))
+            .collect::<Vec<_>>();
+        for host in buttons {
+            self.refresh(host, processor);
+        }
+    }
+
+    pub fn handle_keypress(&mut self, processor: &mut Processor) -> Vec<Action> {
+        let mut actions = Vec::new();
+
+        let events = self.event_pump.poll_iter().collect::<Vec<_>>();
+        for event in events {
             match event {
-                Event::Quit { timestamp: _ } => {
-                    std::process::exit(0);
+                Event::Quit { timestamp: _ } => actions.push(Action::Quit),
+
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } if self.memory_editing && memory_editor_action(keycode).is_some() => {
+                    actions.extend(memory_editor_action(keycode))
                 }
 
                 Event::KeyDown {
                     timestamp: _,
                     window_id: _,
-                    keycode,
+                    keycode: Some(keycode),
                     scancode: _,
-                    keymod: _,
-                    repeat: _,
-                } => match keycode.unwrap() {
-                    Keycode::Num1 => is_key_pressed[0x1] = true,
-                    Keycode::Num2 => is_key_pressed[0x2] = true,
-                    Keycode::Num3 => is_key_pressed[0x3] = true,
-                    Keycode::Num4 => is_key_pressed[0xC] = true,
-                    Keycode::Q => is_key_pressed[0x4] = true,
-                    Keycode::W => is_key_pressed[0x5] = true,
-                    Keycode::E => is_key_pressed[0x6] = true,
-                    Keycode::R => is_key_pressed[0xD] = true,
-                    Keycode::A => is_key_pressed[0x7] = true,
-                    Keycode::S => is_key_pressed[0x8] = true,
-                    Keycode::D => is_key_pressed[0x9] = true,
-                    Keycode::F => is_key_pressed[0xE] = true,
-                    Keycode::Z => is_key_pressed[0xA] = true,
-                    Keycode::X => is_key_pressed[0x0] = true,
-                    Keycode::C => is_key_pressed[0xB] = true,
-                    Keycode::V => is_key_pressed[0xF] = true,
-                    Keycode::Escape => std::process::exit(0),
-                    _ => {}
+                    keymod,
+                    repeat,
+                } => match keycode {
+                    Keycode::Escape => actions.push(Action::Quit),
+                    Keycode::F1 if !repeat && ctrl(keymod) => {
+                        actions.push(Action::TogglePanel(Panel::Registers))
+                    }
+                    Keycode::F2 if !repeat && ctrl(keymod) => {
+                        actions.push(Action::TogglePanel(Panel::Stack))
+                    }
+                    Keycode::F3 if !repeat && ctrl(keymod) => {
+                        actions.push(Action::TogglePanel(Panel::Disassembly))
+                    }
+                    Keycode::F4 if !repeat && ctrl(keymod) => {
+                        actions.push(Action::TogglePanel(Panel::Keypad))
+                    }
+                    Keycode::F5 if !repeat && ctrl(keymod) => {
+                        actions.push(Action::TogglePanel(Panel::Counters))
+                    }
+                    Keycode::F6 if !repeat && ctrl(keymod) => {
+                        actions.push(Action::TogglePanel(Panel::Sprites))
+                    }
+                    Keycode::F1 if !repeat => actions.push(Action::ToggleOverlay),
+                    Keycode::PageUp => actions.push(Action::ScrollMemory(-8)),
+                    Keycode::PageDown => actions.push(Action::ScrollMemory(8)),
+                    Keycode::Home if !repeat => actions.push(Action::ShowPcInMemory),
+                    Keycode::End if !repeat => actions.push(Action::ShowIndexInMemory),
+                    Keycode::Insert if !repeat => actions.push(Action::ToggleMemoryEditing),
+                    Keycode::F2 if !repeat => actions.push(Action::NextTheme),
+                    Keycode::F3 if !repeat => actions.push(Action::ToggleScaleMode),
+                    Keycode::F4 if !repeat => actions.push(Action::NextEffect),
+                    Keycode::F5 if !repeat => actions.push(Action::NextUpscaler),
+                    Keycode::F6 | Keycode::Pause if !repeat => actions.push(Action::TogglePause),
+                    Keycode::F7 => actions.push(Action::AdvanceFrame),
+                    Keycode::F10 if !repeat && shift(keymod) => actions.push(Action::HardReset),
+                    Keycode::F10 if !repeat => actions.push(Action::SoftReset),
+                    Keycode::Tab if !repeat && !self.is_bound(Keycode::Tab) => {
+                        actions.push(Action::Turbo(true))
+                    }
+                    Keycode::Backquote if !repeat && !self.is_bound(Keycode::Backquote) => {
+                        actions.push(Action::ToggleSlowMotion)
+                    }
+                    Keycode::F8 if !repeat && shift(keymod) => {
+                        actions.push(Action::SkipButtonMapping)
+                    }
+                    Keycode::F8 if !repeat => actions.push(Action::ToggleButtonMapping),
+                    Keycode::F9 if !repeat => actions.push(Action::ToggleRecording {
+                        video: shift(keymod),
+                    }),
+                    Keycode::F11 if !repeat => actions.push(Action::ToggleFullscreen),
+                    Keycode::F12 if !repeat && ctrl(keymod) => {
+                        actions.push(Action::SaveSpriteSheet)
+                    }
+                    Keycode::F12 if !repeat => actions.push(Action::Screenshot {
+                        native: shift(keymod),
+                    }),
+                    other => self.set_host(None, Host::Key(other), true, processor),
                 },
 
                 Event::KeyUp {
-                    timestamp: _,
-                    window_id: _,
-                    keycode,
-                    scancode: _,
-                    keymod: _,
-                    repeat: _,
-                } => match keycode.unwrap() {
-                    Keycode::Num1 => is_key_pressed[0x1] = false,
-                    Keycode::Num2 => is_key_pressed[0x2] = false,
-                    Keycode::Num3 => is_key_pressed[0x3] = false,
-                    Keycode::Num4 => is_key_pressed[0xC] = false,
-                    Keycode::Q => is_key_pressed[0x4] = false,
-                    Keycode::W => is_key_pressed[0x5] = false,
-                    Keycode::E => is_key_pressed[0x6] = false,
-                    Keycode::R => is_key_pressed[0xD] = false,
-                    Keycode::A => is_key_pressed[0x7] = false,
-                    Keycode::S => is_key_pressed[0x8] = false,
-                    Keycode::D => is_key_pressed[0x9] = false,
-                    Keycode::F => is_key_pressed[0xE] = false,
-                    Keycode::Z => is_key_pressed[0xA] = false,
-                    Keycode::X => is_key_pressed[0x0] = false,
-                    Keycode::C => is_key_pressed[0xB] = false,
-                    Keycode::V => is_key_pressed[0xF] = false,
-                    _ => {}
-                },
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } if !self.is_bound(Keycode::Tab) => actions.push(Action::Turbo(false)),
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => self.set_host(None, Host::Key(keycode), false, processor),
+
+                Event::ControllerDeviceAdded { which, .. } => self.add_controller(which),
+                Event::ControllerDeviceRemoved { which, .. } => {
+                    self.remove_controller(which, processor)
+                }
+                Event::ControllerButtonDown { button, .. } if self.button_mapping => {
+                    actions.push(Action::MapButton(button))
+                }
+                Event::ControllerButtonDown { which, button, .. } => {
+                    self.set_host(Some(which), Host::Button(button), true, processor)
+                }
+                Event::ControllerButtonUp { which, button, .. } => {
+                    self.set_host(Some(which), Host::Button(button), false, processor)
+                }
+                Event::ControllerAxisMotion {
+                    which, axis, value, ..
+                } => self.set_stick(which, axis, value, processor),
+
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                    ..
+                } => actions.push(Action::Redraw),
 
                 _ => {}
             }
         }
+
+        actions
     }
 }
+
+fn ctrl(keymod: Mod) -> bool {
+    keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD)
+}
+
+fn shift(keymod: Mod) -> bool {
+    keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD)
+}
+
+fn memory_editor_action(keycode: Keycode) -> Option<Action> {
+    let nibble = match keycode {
+        Keycode::Left => return Some(Action::MoveMemoryCursor(-1)),
+        Keycode::Right => return Some(Action::MoveMemoryCursor(1)),
+        Keycode::Up => return Some(Action::MoveMemoryCursor(-4)),
+        Keycode::Down => return Some(Action::MoveMemoryCursor(4)),
+        Keycode::Num0 => 0x0,
+        Keycode::Num1 => 0x1,
+        Keycode::Num2 => 0x2,
+        Keycode::Num3 => 0x3,
+        Keycode::Num4 => 0x4,
+        Keycode::Num5 => 0x5,
+        Keycode::Num6 => 0x6,
+        Keycode::Num7 => 0x7,
+        Keycode::Num8 => 0x8,
+        Keycode::Num9 => 0x9,
+        Keycode::A => 0xA,
+        Keycode::B => 0xB,
+        Keycode::C => 0xC,
+        Keycode::D => 0xD,
+        Keycode::E => 0xE,
+        Keycode::F => 0xF,
+        _ => return None,
+    };
+
+    Some(Action::EnterNibble(nibble))
+}
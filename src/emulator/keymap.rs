@@ -0,0 +1,251 @@
+use crate::emulator::consts;
+use std::collections::BTreeMap;
+
+pub const KEYMAPS: [&str; 5] = ["qwerty", "azerty", "dvorak", "numpad", "cosmac"];
+
+// Host keys in the order of the COSMAC VIP keypad:
+// 1 2 3 C
+// 4 5 6 D
+// 7 8 9 E
+// A 0 B F
+const KEYPAD_ORDER: [usize; consts::N_KEYS] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+const QWERTY: [&str; consts::N_KEYS] = [
+    "1", "2", "3", "4", "Q", "W", "E", "R", "A", "S", "D", "F", "Z", "X", "C", "V",
+];
+const AZERTY: [&str; consts::N_KEYS] = [
+    "1", "2", "3", "4", "A", "Z", "E", "R", "Q", "S", "D", "F", "W", "X", "C", "V",
+];
+const DVORAK: [&str; consts::N_KEYS] = [
+    "1", "2", "3", "4", "'", ",", ".", "P", "A", "O", "E", "U", ";", "Q", "J", "K",
+];
+// Digits on their own number, A-F on the keys around them.
+const NUMPAD: [&str; consts::N_KEYS] = [
+    "Keypad 1",
+    "Keypad 2",
+    "Keypad 3",
+    "Keypad -",
+    "Keypad 4",
+    "Keypad 5",
+    "Keypad 6",
+    "Keypad +",
+    "Keypad 7",
+    "Keypad 8",
+    "Keypad 9",
+    "Keypad Enter",
+    "Keypad /",
+    "Keypad 0",
+    "Keypad *",
+    "Keypad .",
+];
+// Every key labelled with its hex digit.
+const COSMAC: [&str; consts::N_KEYS] = [
+    "1", "2", "3", "C", "4", "5", "6", "D", "7", "8", "9", "E", "A", "0", "B", "F",
+];
+
+// Controller buttons by SDL game controller name, the same for every
+// profile: the D-pad (and left stick) on the WASD-style 5/7/8/9 cluster.
+const BUTTONS: [(&str, usize); 6] = [
+    ("dpup", 0x5),
+    ("dpdown", 0x8),
+    ("dpleft", 0x7),
+    ("dpright", 0x9),
+    ("a", 0x6),
+    ("b", 0x4),
+];
+
+// Which host keys, by SDL key name, and controller buttons press which
+// CHIP-8 keys. A CHIP-8 key can have any number of host keys and buttons but
+// each of those presses only one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keymap {
+    bindings: Vec<(String, usize)>,
+    buttons: Vec<(String, usize)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::profile(KEYMAPS[0]).unwrap()
+    }
+}
+
+impl Keymap {
+    pub fn profile(name: &str) -> Option<Keymap> {
+        let keys = match name {
+            "qwerty" => QWERTY,
+            "azerty" => AZERTY,
+            "dvorak" => DVORAK,
+            "numpad" => NUMPAD,
+            "cosmac" => COSMAC,
+            _ => return None,
+        };
+
+        Some(Keymap {
+            bindings: keys
+                .iter()
+                .zip(KEYPAD_ORDER)
+                .map(|(host, key)| (host.to_string(), key))
+                .collect(),
+            buttons: BUTTONS
+                .iter()
+                .map(|(button, key)| (button.to_string(), *key))
+                .collect(),
+        })
+    }
+
+    pub fn bindings(&self) -> &[(String, usize)] {
+        &self.bindings
+    }
+
+    pub fn button_bindings(&self) -> &[(String, usize)] {
+        &self.buttons
+    }
+
+    // Key names are compared case-insensitively, like SDL does.
+    pub fn key(&self, host: &str) -> Option<usize> {
+        find(&self.bindings, host)
+    }
+
+    pub fn button(&self, button: &str) -> Option<usize> {
+        find(&self.buttons, button)
+    }
+
+    // Takes `host` away from whichever key it pressed before.
+    pub fn bind(&mut self, host: &str, key: usize) {
+        bind(&mut self.bindings, host, key);
+    }
+
+    pub fn bind_button(&mut self, button: &str, key: usize) {
+        bind(&mut self.buttons, button, key);
+    }
+
+    // Replaces the host keys of each CHIP-8 key in `keys`, which maps hex
+    // digits to key names, e.g. `{"5": ["W", "Up"]}`. Unknown digits are
+    // skipped.
+    pub fn rebind(&mut self, keys: &BTreeMap<String, Vec<String>>) {
+        rebind(&mut self.bindings, keys);
+    }
+
+    // Like `rebind`, with controller button names such as "dpup" or "x".
+    pub fn rebind_buttons(&mut self, buttons: &BTreeMap<String, Vec<String>>) {
+        rebind(&mut self.buttons, buttons);
+    }
+
+    // The button bindings in the form `rebind_buttons` takes.
+    pub fn buttons_by_key(&self) -> BTreeMap<String, Vec<String>> {
+        let mut buttons = BTreeMap::<String, Vec<String>>::new();
+        for (button, key) in &self.buttons {
+            buttons
+                .entry(format!("{:X}", key))
+                .or_default()
+                .push(button.clone());
+        }
+
+        buttons
+    }
+
+    // Binds the semantic keys of a ROM database entry ("up", "a", ...) to the
+    // arrow keys, space and return, and to the D-pad and A and B buttons.
+    pub fn bind_rom_keys(&mut self, keys: &[(String, u8)]) {
+        for (name, key) in keys {
+            let (host, button) = match name.as_str() {
+                "up" => ("Up", "dpup"),
+                "down" => ("Down", "dpdown"),
+                "left" => ("Left", "dpleft"),
+                "right" => ("Right", "dpright"),
+                "a" => ("Space", "a"),
+                "b" => ("Return", "b"),
+                _ => continue,
+            };
+            self.bind(host, *key as usize);
+            self.bind_button(button, *key as usize);
+        }
+    }
+}
+
+fn find(bindings: &[(String, usize)], name: &str) -> Option<usize> {
+    bindings
+        .iter()
+        .find(|(bound, _)| bound.eq_ignore_ascii_case(name))
+        .map(|(_, key)| *key)
+}
+
+fn bind(bindings: &mut Vec<(String, usize)>, name: &str, key: usize) {
+    bindings.retain(|(bound, _)| !bound.eq_ignore_ascii_case(name));
+    bindings.push((name.to_string(), key % consts::N_KEYS));
+}
+
+fn rebind(bindings: &mut Vec<(String, usize)>, keys: &BTreeMap<String, Vec<String>>) {
+    for (digit, names) in keys {
+        let Ok(key) = usize::from_str_radix(digit, 16) else {
+            continue;
+        };
+        if key >= consts::N_KEYS {
+            continue;
+        }
+        bindings.retain(|(_, bound)| *bound != key);
+        for name in names {
+            bind(bindings, name, key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn keymap_test() {
+        let qwerty = Keymap::default();
+        assert_eq!(qwerty.key("q"), Some(0x4));
+        assert_eq!(qwerty.key("V"), Some(0xF));
+        assert_eq!(qwerty.key("Up"), None);
+
+        let azerty = Keymap::profile("azerty").unwrap();
+        assert_eq!(azerty.key("Z"), Some(0x5));
+        assert_eq!(azerty.key("W"), Some(0xA));
+        assert_eq!(
+            Keymap::profile("numpad").unwrap().key("Keypad 8"),
+            Some(0x8)
+        );
+        assert_eq!(Keymap::profile("cosmac").unwrap().key("B"), Some(0xB));
+        assert!(Keymap::profile("colemak").is_none());
+
+        let mut keymap = Keymap::default();
+        keymap.rebind(&BTreeMap::from([
+            ("5".to_string(), vec!["W".to_string(), "I".to_string()]),
+            ("g".to_string(), vec!["G".to_string()]),
+        ]));
+        keymap.bind_rom_keys(&[("up".to_string(), 5), ("a".to_string(), 6)]);
+        assert_eq!(keymap.key("W"), Some(0x5));
+        assert_eq!(keymap.key("I"), Some(0x5));
+        assert_eq!(keymap.key("Up"), Some(0x5));
+        assert_eq!(keymap.key("Space"), Some(0x6));
+        assert_eq!(keymap.key("G"), None);
+        assert_eq!(keymap.bindings().len(), consts::N_KEYS + 3);
+        assert_eq!(keymap.button("dpup"), Some(0x5));
+        assert_eq!(keymap.button("a"), Some(0x6));
+    }
+
+    #[test]
+    fn button_test() {
+        let mut keymap = Keymap::default();
+        assert_eq!(keymap.button("dpleft"), Some(0x7));
+        assert_eq!(keymap.button("x"), None);
+
+        keymap.rebind_buttons(&BTreeMap::from([(
+            "7".to_string(),
+            vec!["x".to_string(), "leftshoulder".to_string()],
+        )]));
+        keymap.bind_button("a", 0x7);
+        assert_eq!(keymap.button("dpleft"), None);
+        assert_eq!(keymap.button("X"), Some(0x7));
+
+        let buttons = keymap.buttons_by_key();
+        assert_eq!(buttons["7"], vec!["x", "leftshoulder", "a"]);
+        assert_eq!(buttons["5"], vec!["dpup"]);
+        assert!(!buttons.contains_key("6"));
+    }
+}
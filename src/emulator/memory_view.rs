@@ -0,0 +1,219 @@
+use crate::emulator::consts;
+use crate::emulator::processor::Processor;
+
+pub const BYTES_PER_ROW: usize = 4;
+pub const VIEW_ROWS: usize = 8;
+// Bytes written within this many cycles count as recently written.
+pub const RECENT_WRITE_CYCLES: u64 = 360;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ByteKind {
+    Normal,
+    Pc,
+    Index,
+    RecentlyWritten,
+    Cursor,
+}
+
+// A scrollable window onto memory with a cursor for in-place editing. Edits
+// go through `Processor::poke`, the same path the program's writes take.
+#[derive(Debug)]
+pub struct MemoryView {
+    start: u16,
+    cursor: u16,
+    editing: bool,
+    high_nibble: Option<u8>,
+}
+
+impl Default for MemoryView {
+    fn default() -> MemoryView {
+        MemoryView::new()
+    }
+}
+
+impl MemoryView {
+    pub fn new() -> MemoryView {
+        MemoryView {
+            start: consts::ROM_START as u16,
+            cursor: consts::ROM_START as u16,
+            editing: false,
+            high_nibble: None,
+        }
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn cursor(&self) -> u16 {
+        self.cursor
+    }
+
+    pub fn is_editing(&self) -> bool {
+        self.editing
+    }
+
+    pub fn toggle_editing(&mut self) {
+        self.editing = !self.editing;
+        self.high_nibble = None;
+    }
+
+    pub fn scroll(&mut self, rows: i32) {
+        let start = self.start as i32 + rows * BYTES_PER_ROW as i32;
+        self.start = start.clamp(0, last_start() as i32) as u16;
+        self.cursor = self.cursor.clamp(self.start, (self.end() - 1) as u16);
+    }
+
+    // Shows the row holding `addr` at the top and puts the cursor on it.
+    pub fn jump(&mut self, addr: u16) {
+        self.cursor = addr.min((consts::XO_MEMORY_SIZE - 1) as u16);
+        self.start = (self.cursor - self.cursor % BYTES_PER_ROW as u16).min(last_start());
+    }
+
+    pub fn move_cursor(&mut self, offset: i32) {
+        let cursor = (self.cursor as i32 + offset).clamp(0, consts::XO_MEMORY_SIZE as i32 - 1);
+        self.cursor = cursor as u16;
+        self.high_nibble = None;
+        if self.cursor < self.start {
+            self.start = self.cursor - self.cursor % BYTES_PER_ROW as u16;
+        } else if self.cursor as usize >= self.end() {
+            let row = self.cursor - self.cursor % BYTES_PER_ROW as u16;
+            self.start = row + BYTES_PER_ROW as u16 - (VIEW_ROWS * BYTES_PER_ROW) as u16;
+        }
+    }
+
+    // Two nibbles make a byte, after which the cursor moves on.
+    pub fn enter_nibble(&mut self, processor: &mut Processor, nibble: u8) {
+        if !self.editing {
+            return;
+        }
+
+        match self.high_nibble.take() {
+            None => self.high_nibble = Some(nibble & 0xF),
+            Some(high) => {
+                processor.poke(self.cursor, high << 4 | (nibble & 0xF));
+                self.move_cursor(1);
+            }
+        }
+    }
+
+    // What a byte should be highlighted as, most important first.
+    pub fn byte_kind(&self, processor: &Processor, addr: u16) -> ByteKind {
+        if self.editing && addr == self.cursor {
+            ByteKind::Cursor
+        } else if addr == processor.pc() || addr == processor.pc().wrapping_add(1) {
+            ByteKind::Pc
+        } else if addr == processor.index() {
+            ByteKind::Index
+        } else if processor
+            .last_write(addr)
+            .is_some_and(|cycle| processor.cycles() - cycle < RECENT_WRITE_CYCLES)
+        {
+            ByteKind::RecentlyWritten
+        } else {
+            ByteKind::Normal
+        }
+    }
+
+    // One past the last byte shown, which is past the end of memory on the
+    // last page.
+    fn end(&self) -> usize {
+        self.start as usize + VIEW_ROWS * BYTES_PER_ROW
+    }
+}
+
+fn last_start() -> u16 {
+    (consts::XO_MEMORY_SIZE - VIEW_ROWS * BYTES_PER_ROW) as u16
+}
+
+pub fn ascii(byte: u8) -> char {
+    if byte.is_ascii_graphic() || byte == b' ' {
+        byte as char
+    } else {
+        '.'
+    }
+}
+
+// A byte as a row of sprite pixels.
+pub fn sprite_row(byte: u8) -> String {
+    (0..8)
+        .map(|bit| if byte & (0x80 >> bit) != 0 { '#' } else { '.' })
+        .collect()
+}
+
+// A plain text dump for consoles: address, hex, ASCII and one sprite row per
+// byte.
+pub fn dump(processor: &Processor, start: u16, len: usize) -> Vec<String> {
+    let memory = processor.memory();
+    let end = (start as usize + len).min(memory.len());
+
+    (start as usize..end)
+        .step_by(BYTES_PER_ROW)
+        .map(|addr| {
+            let bytes = &memory[addr..(addr + BYTES_PER_ROW).min(end)];
+            let hex = bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let text = bytes.iter().map(|byte| ascii(*byte)).collect::<String>();
+            let sprite = bytes
+                .iter()
+                .map(|byte| sprite_row(*byte))
+                .collect::<Vec<_>>()
+                .join(" ");
+            format!(
+                "{:04X}  {:<width$}  {:<4}  {}",
+                addr,
+                hex,
+                text,
+                sprite,
+                width = BYTES_PER_ROW * 3 - 1
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn memory_view_test() {
+        let mut processor = Processor::new();
+        processor.load_rom_bytes("test", &[0x60, 0x41]).unwrap();
+
+        let mut view = MemoryView::new();
+        view.enter_nibble(&mut processor, 0xF);
+        assert_eq!(processor.memory()[0x200], 0x60);
+
+        view.toggle_editing();
+        view.move_cursor(2);
+        view.enter_nibble(&mut processor, 0xF);
+        view.enter_nibble(&mut processor, 0x0);
+        assert_eq!(processor.memory()[0x202], 0xF0);
+        assert_eq!(view.cursor(), 0x203);
+        assert_eq!(view.byte_kind(&processor, 0x202), ByteKind::RecentlyWritten);
+        assert_eq!(view.byte_kind(&processor, 0x203), ByteKind::Cursor);
+        assert_eq!(view.byte_kind(&processor, 0x201), ByteKind::Pc);
+        assert!(processor.self_modifying_code_report().is_empty());
+
+        view.move_cursor(32);
+        assert_eq!(view.start(), 0x204);
+        view.scroll(-200);
+        assert_eq!((view.start(), view.cursor()), (0, 0x1F));
+        view.jump(0xFFFF);
+        assert_eq!(view.start(), 0xFFE0);
+        view.move_cursor(1);
+        assert_eq!((view.start(), view.cursor()), (0xFFE0, 0xFFFF));
+        view.scroll(-2);
+        view.scroll(VIEW_ROWS as i32);
+        assert_eq!((view.start(), view.cursor()), (0xFFE0, 0xFFF7));
+
+        assert_eq!(
+            dump(&processor, 0x200, 4),
+            vec!["0200  60 41 F0 00  `A..  .##..... .#.....# ####.... ........"]
+        );
+    }
+}
@@ -1,35 +1,646 @@
+pub mod analysis;
+pub mod audio;
+pub mod cartridge;
+pub mod config;
 pub mod consts;
+pub mod debugger;
+#[cfg(feature = "sdl")]
 pub mod display;
+pub mod effects;
+pub mod font;
+pub mod frame;
+#[cfg(feature = "sdl")]
+mod frontend;
+#[cfg(feature = "sdl")]
 pub mod input;
+pub mod keymap;
+pub mod memory_view;
+pub mod octo;
+pub mod overlay;
+pub mod palette;
+pub mod persistence;
+pub mod pipeline;
+#[cfg(feature = "sdl")]
+pub mod platform;
 pub mod processor;
+pub mod recorder;
+pub mod rom;
+pub mod rom_db;
+pub mod screenshot;
+pub mod sprites;
+pub mod terminal;
+pub mod upscale;
+
+use palette::Palette;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Why `Emulator::run` returned. The emulator can be run again afterwards.
+#[derive(Debug)]
+pub enum RunOutcome {
+    Quit,
+    // The ROM executed EXIT (00FD).
+    Halted,
+    // The cycle starting at `pc` in emulated frame `frame` failed.
+    Error {
+        error: processor::ProcessorError,
+        pc: u16,
+        frame: u64,
+    },
+    FrameLimit,
+}
+
+impl fmt::Display for RunOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunOutcome::Quit => write!(f, "quit"),
+            RunOutcome::Halted => write!(f, "ROM halted"),
+            RunOutcome::Error { error, pc, frame } => {
+                write!(f, "frame {}, PC {:04X}: {}", frame, pc, error)
+            }
+            RunOutcome::FrameLimit => write!(f, "frame limit reached"),
+        }
+    }
+}
 
 pub struct Emulator {
+    // None when headless.
+    #[cfg(feature = "sdl")]
+    frontend: Option<frontend::Frontend>,
     processor: processor::Processor,
-    display: display::Display,
-    input: input::Input,
+    tickrate: usize,
+    // Instructions run so far in the current frame.
+    frame_cycles: usize,
+    pipeline: pipeline::Pipeline,
+    rom_name: Option<String>,
+    frame_count: u64,
+    screenshot_dir: PathBuf,
+    screenshot_metadata: bool,
+    recorder: Option<recorder::Recorder>,
+    overlay: overlay::Overlay,
+    // The configured keymap, and the one in use with the ROM's bindings.
+    keymap: keymap::Keymap,
+    active_keymap: keymap::Keymap,
+    rom_path: Option<String>,
+    paused: bool,
+    // A frame to run while paused.
+    frame_advance: bool,
+    turbo: bool,
+    turbo_multiplier: u32,
+    slow_motion: bool,
+    slow_motion_divisor: u32,
+    // Real frames since slow motion last ran an emulated one.
+    slow_motion_wait: u32,
+    frame_limit: Option<u64>,
 }
 
 impl Emulator {
-    pub fn new() -> Emulator {
+    // An emulator without SDL, window or config file, for hosts that drive
+    // it with `step`, `run_frame` and `run_until` and read its state back.
+    // Keys are pressed with `set_key`.
+    pub fn headless() -> Emulator {
         Emulator {
+            #[cfg(feature = "sdl")]
+            frontend: None,
             processor: processor::Processor::new(),
-            display: display::Display::new(),
-            input: input::Input::new(),
+            tickrate: consts::DEFAULT_TICKRATE,
+            frame_cycles: 0,
+            pipeline: pipeline::Pipeline::new(),
+            rom_name: None,
+            frame_count: 0,
+            screenshot_dir: PathBuf::from("."),
+            screenshot_metadata: true,
+            recorder: None,
+            overlay: overlay::Overlay::new(),
+            keymap: keymap::Keymap::default(),
+            active_keymap: keymap::Keymap::default(),
+            rom_path: None,
+            paused: false,
+            frame_advance: false,
+            turbo: false,
+            turbo_multiplier: consts::DEFAULT_TURBO_MULTIPLIER,
+            slow_motion: false,
+            slow_motion_divisor: consts::DEFAULT_SLOW_MOTION_DIVISOR,
+            slow_motion_wait: 0,
+            frame_limit: None,
+        }
+    }
+
+    pub fn load_rom(&mut self, rom_path: &str) -> Result<(), processor::ProcessorError> {
+        let settings = self.processor.load_rom(rom_path)?;
+        self.rom_name = Path::new(rom_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        self.rom_path = Some(rom_path.to_string());
+        self.apply_rom_settings(settings);
+
+        Ok(())
+    }
+
+    // Resets cannot reload a ROM given as bytes from disk, a hard reset
+    // restarts it like a soft one.
+    pub fn load_rom_bytes(
+        &mut self,
+        name: &str,
+        rom: &[u8],
+    ) -> Result<(), processor::ProcessorError> {
+        let settings = self.processor.load_rom_bytes(name, rom)?;
+        self.rom_name = Some(name.to_string());
+        self.rom_path = None;
+        self.apply_rom_settings(settings);
+
+        Ok(())
+    }
+
+    fn apply_rom_settings(&mut self, settings: rom_db::RomSettings) {
+        self.frame_count = 0;
+        self.frame_cycles = 0;
+        self.tickrate = settings.tickrate;
+        if let Some(colors) = settings.colors {
+            self.set_palette(Palette::new(colors.to_vec()).unwrap());
+        }
+        // A ROM's own profile replaces the configured one.
+        let mut keymap = settings
+            .keymap
+            .as_deref()
+            .and_then(keymap::Keymap::profile)
+            .unwrap_or_else(|| self.keymap.clone());
+        keymap.bind_rom_keys(&settings.keys);
+        #[cfg(feature = "sdl")]
+        if let Some(frontend) = &mut self.frontend {
+            frontend.input.set_keymap(&keymap);
+        }
+        self.active_keymap = keymap;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+        self.frame_advance = false;
+    }
+
+    // Runs a single frame and stays paused.
+    pub fn advance_frame(&mut self) {
+        self.paused = true;
+        self.frame_advance = true;
+    }
+
+    // How many emulated frames fast-forward runs per real one.
+    pub fn set_turbo_multiplier(&mut self, multiplier: u32) {
+        self.turbo_multiplier = multiplier.max(1);
+    }
+
+    // How many real frames slow motion spreads each emulated one over.
+    pub fn set_slow_motion_divisor(&mut self, divisor: u32) {
+        self.slow_motion_divisor = divisor.max(1);
+    }
+
+    // Makes `run` return after this many emulated frames.
+    pub fn set_frame_limit(&mut self, frames: Option<u64>) {
+        self.frame_limit = frames;
+    }
+
+    pub fn processor(&self) -> &processor::Processor {
+        &self.processor
+    }
+
+    // For tools that change registers, memory or the stack between steps.
+    pub fn processor_mut(&mut self) -> &mut processor::Processor {
+        &mut self.processor
+    }
+
+    // The 64x32 framebuffer, one palette index per pixel, row by row.
+    pub fn framebuffer(&self) -> &[u8] {
+        &self.processor.display
+    }
+
+    pub fn registers(&self) -> &[u8; consts::N_REGISTERS] {
+        self.processor.registers()
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        self.processor.memory()
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.processor.pc()
+    }
+
+    pub fn index(&self) -> u16 {
+        self.processor.index()
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.processor.delay_timer()
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.processor.sound_timer()
+    }
+
+    // Emulated frames since the ROM was loaded.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    pub fn set_key(&mut self, key: usize, pressed: bool) -> Result<(), processor::ProcessorError> {
+        self.processor.set_key(key, pressed)
+    }
+
+    // Restarts the loaded program without reading the ROM again.
+    pub fn soft_reset(&mut self) {
+        self.processor.reset();
+    }
+
+    // Reloads the ROM from disk into a fresh processor, picking up its
+    // database settings again. Keys still held stay pressed. If the ROM can't
+    // be read any more, the old machine keeps running.
+    pub fn hard_reset(&mut self) -> Result<(), processor::ProcessorError> {
+        let Some(rom_path) = self.rom_path.clone() else {
+            self.soft_reset();
+            return Ok(());
+        };
+        let mut processor = processor::Processor::new();
+        processor.self_modifying_code_policy = self.processor.self_modifying_code_policy;
+        processor.keys_pressed = self.processor.keys_pressed;
+        let settings = processor.load_rom(&rom_path)?;
+        self.processor = processor;
+        self.apply_rom_settings(settings);
+        #[cfg(feature = "sdl")]
+        if let Some(display) = self.display_mut() {
+            display.invalidate();
+        }
+
+        Ok(())
+    }
+
+    // ROMs loaded afterwards get their database keys bound on top.
+    pub fn set_keymap(&mut self, keymap: keymap::Keymap) {
+        #[cfg(feature = "sdl")]
+        if let Some(frontend) = &mut self.frontend {
+            frontend.input.set_keymap(&keymap);
         }
+        self.active_keymap = keymap.clone();
+        self.keymap = keymap;
+    }
+
+    // The keymap in use, with the loaded ROM's bindings.
+    pub fn active_keymap(&self) -> &keymap::Keymap {
+        &self.active_keymap
     }
 
-    pub fn load_rom(&mut self, rom_path: &str) {
-        match self.processor.load_rom(rom_path) {
-            Ok(_) => (),
-            Err(err) => println!("{}", err),
+    pub fn palette(&self) -> &Palette {
+        self.pipeline.palette()
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.update_pipeline(|pipeline| pipeline.set_palette(palette.clone()));
+    }
+
+    pub fn set_persistence(&mut self, mode: persistence::PersistenceMode) {
+        self.update_pipeline(|pipeline| pipeline.set_persistence(mode));
+    }
+
+    pub fn set_effect(&mut self, effect: effects::Effect) {
+        self.update_pipeline(|pipeline| pipeline.set_effect(effect));
+    }
+
+    pub fn set_upscaler(&mut self, upscaler: upscale::Upscaler) {
+        self.update_pipeline(|pipeline| pipeline.set_upscaler(upscaler));
+    }
+
+    // The window and a running recording show the same filters.
+    fn update_pipeline(&mut self, update: impl Fn(&mut pipeline::Pipeline)) {
+        update(&mut self.pipeline);
+        #[cfg(feature = "sdl")]
+        if let Some(display) = self.display_mut() {
+            update(display.pipeline_mut());
         }
+        if let Some(recorder) = &mut self.recorder {
+            update(recorder.pipeline_mut());
+        }
+    }
+
+    pub fn set_screenshot_dir(&mut self, dir: &Path) {
+        self.screenshot_dir = dir.to_path_buf();
     }
 
-    pub fn run(&mut self) {
+    // Whether screenshots carry the ROM name and frame number as text chunks.
+    pub fn set_screenshot_metadata(&mut self, metadata: bool) {
+        self.screenshot_metadata = metadata;
+    }
+
+    // Either the bare 64x32 framebuffer in the current palette or what the
+    // window shows, with scaling and every filter applied. Headless
+    // emulators only have the former.
+    pub fn screenshot(&self, native: bool) -> frame::Frame {
+        if !native {
+            #[cfg(feature = "sdl")]
+            if let Some(display) = self.display() {
+                return display.screenshot();
+            }
+        }
+
+        frame::Frame::from_indices(
+            &self.processor.display,
+            consts::DISPLAY_WIDTH,
+            consts::DISPLAY_HEIGHT,
+            self.pipeline.palette(),
+        )
+    }
+
+    pub fn save_screenshot(&self, native: bool) -> io::Result<PathBuf> {
+        let metadata = if self.screenshot_metadata {
+            screenshot::Metadata {
+                rom: self.rom_name.clone(),
+                frame: Some(self.frame_count),
+            }
+        } else {
+            screenshot::Metadata::default()
+        };
+
+        screenshot::save_png(&self.screenshot_dir, &self.screenshot(native), &metadata)
+    }
+
+    // Every sprite drawn since the ROM was loaded, one per cell.
+    pub fn save_sprite_sheet(&self) -> io::Result<PathBuf> {
+        let sheet = sprites::sprite_sheet(
+            self.processor.memory(),
+            self.processor.drawn_sprites(),
+            sprites::SHEET_COLUMNS,
+        );
+        if sheet.pixels.is_empty() {
+            return Err(io::Error::other("No sprites drawn yet"));
+        }
+        let metadata = screenshot::Metadata {
+            rom: self.rom_name.clone(),
+            frame: None,
+        };
+
+        screenshot::save_png(&self.screenshot_dir, &sheet, &metadata)
+    }
+
+    pub fn toggle_overlay(&mut self) {
+        self.overlay.toggle();
+        #[cfg(feature = "sdl")]
+        self.sync_overlay();
+    }
+
+    pub fn toggle_overlay_panel(&mut self, panel: overlay::Panel) {
+        self.overlay.toggle_panel(panel);
+        #[cfg(feature = "sdl")]
+        self.sync_overlay();
+    }
+
+    // Any recording already running is finished first.
+    pub fn start_recording(&mut self, path: &Path) -> io::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(recorder::Recorder::create(path, self.pipeline.fresh())?);
+
+        Ok(())
+    }
+
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(recorder) => recorder.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    fn record_frame(&mut self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        let result = recorder.record(&self.processor.display, self.processor.sound_active());
+        if let Err(err) = result {
+            println!("{}", err);
+            // Still write out the sizes of what was recorded so far.
+            if let Err(err) = self.stop_recording() {
+                println!("{}", err);
+            }
+        }
+    }
+
+    pub fn self_modifying_code_report(&self) -> &[processor::CodeWrite] {
+        self.processor.self_modifying_code_report()
+    }
+
+    pub fn set_self_modifying_code_policy(&mut self, policy: processor::SelfModifyingCodePolicy) {
+        self.processor.self_modifying_code_policy = policy;
+    }
+
+    // Fast-forward wins over slow motion, and pausing over both.
+    fn frames_due(&mut self) -> u32 {
+        if self.paused {
+            return std::mem::take(&mut self.frame_advance) as u32;
+        }
+        if self.turbo {
+            return self.turbo_multiplier;
+        }
+        if self.slow_motion {
+            self.slow_motion_wait += 1;
+            if self.slow_motion_wait < self.slow_motion_divisor {
+                return 0;
+            }
+            self.slow_motion_wait = 0;
+        }
+
+        1
+    }
+
+    // Runs one instruction. The frame ends, ticking the timers, once it has
+    // run `tickrate` instructions or the ROM halts, so stepping through a
+    // frame does the same as `run_frame`. Returns the outcome that ends the
+    // run, if any.
+    pub fn step(&mut self) -> Option<RunOutcome> {
+        if self.processor.is_halted() {
+            return Some(RunOutcome::Halted);
+        }
+        let pc = self.processor.pc();
+        if let Err(error) = self.processor.emulate_cycle() {
+            return Some(RunOutcome::Error {
+                error,
+                pc,
+                frame: self.frame_count,
+            });
+        }
+        self.frame_cycles += 1;
+        if self.frame_cycles >= self.tickrate || self.processor.is_halted() {
+            self.end_frame();
+        }
+
+        self.processor.is_halted().then_some(RunOutcome::Halted)
+    }
+
+    // Runs the rest of the current frame, a whole one unless it was
+    // started with `step`.
+    pub fn run_frame(&mut self) -> Option<RunOutcome> {
+        let frame = self.frame_count;
+        while self.frame_count == frame {
+            if let Some(outcome) = self.step() {
+                return Some(outcome);
+            }
+        }
+
+        None
+    }
+
+    // Steps until `done` holds, checking it before every instruction. Returns
+    // the outcome instead if the ROM halts or fails first.
+    pub fn run_until<F>(&mut self, mut done: F) -> Option<RunOutcome>
+    where
+        F: FnMut(&Emulator) -> bool,
+    {
+        while !done(self) {
+            if let Some(outcome) = self.step() {
+                return Some(outcome);
+            }
+        }
+
+        None
+    }
+
+    fn end_frame(&mut self) {
+        self.processor.tick_timers();
+        self.frame_cycles = 0;
+        self.frame_count += 1;
+        self.record_frame();
+    }
+
+    // Recordings are finished unless the run only stopped at the frame
+    // limit, so that it can be continued.
+    fn finish(&mut self, outcome: RunOutcome) -> RunOutcome {
+        if !matches!(outcome, RunOutcome::FrameLimit) {
+            if let Err(err) = self.stop_recording() {
+                println!("{}", err);
+            }
+        }
+
+        outcome
+    }
+
+    // Runs until the user quits, the ROM halts or fails, or the frame limit
+    // is reached.
+    pub fn run(&mut self) -> RunOutcome {
+        let frame_duration = Duration::from_secs(1) / consts::FRAME_RATE;
+        let mut frames_run = 0;
+
         loop {
-            self.processor.emulate_cycle().unwrap();
-            self.display.draw_texture(&self.processor.display);
-            self.input.handle_keypress(&mut self.processor.keys_pressed);
+            let frame_start = Instant::now();
+
+            let frames = self.frames_due();
+            for _ in 0..frames {
+                if let Some(outcome) = self.run_frame() {
+                    return self.finish(outcome);
+                }
+                frames_run += 1;
+                if self.frame_limit.is_some_and(|limit| frames_run >= limit) {
+                    return self.finish(RunOutcome::FrameLimit);
+                }
+            }
+            #[cfg(feature = "sdl")]
+            if let Some(outcome) = self.present(frames) {
+                return self.finish(outcome);
+            }
+
+            if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+                thread::sleep(remaining);
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn step_test() {
+        // LD V0, 3; LD DT, V0; ADD V1, 1; JP 204
+        let rom = [0x60, 0x03, 0xF0, 0x15, 0x71, 0x01, 0x12, 0x04];
+        let mut emulator = Emulator::headless();
+        emulator.load_rom_bytes("test", &rom).unwrap();
+
+        assert!(emulator.step().is_none());
+        assert!(emulator.step().is_none());
+        assert_eq!(emulator.pc(), 0x204);
+        assert_eq!(emulator.delay_timer(), 3);
+
+        // The first frame ends where it would have without stepping.
+        assert!(emulator.run_frame().is_none());
+        assert_eq!(emulator.frame_count(), 1);
+        assert_eq!(emulator.delay_timer(), 2);
+        let cycles = emulator.processor().cycles();
+        assert_eq!(cycles, emulator.tickrate as u64);
+
+        assert!(emulator
+            .run_until(|emulator| emulator.delay_timer() == 0)
+            .is_none());
+        assert_eq!(emulator.frame_count(), 3);
+        // Every other instruction after the first two adds one.
+        assert_eq!(
+            emulator.registers()[1] as u64,
+            (3 * cycles - 2).div_ceil(2) % 256
+        );
+        assert_eq!(
+            emulator.framebuffer().len(),
+            consts::DISPLAY_WIDTH * consts::DISPLAY_HEIGHT
+        );
+    }
+
+    #[test]
+    fn fault_test() {
+        // LD V0, 3; RET
+        let mut emulator = Emulator::headless();
+        emulator
+            .load_rom_bytes("test", &[0x60, 0x03, 0x00, 0xEE])
+            .unwrap();
+
+        let outcome = emulator.run_frame();
+        assert!(matches!(
+            outcome,
+            Some(RunOutcome::Error {
+                error: processor::ProcessorError::StackEmpty,
+                pc: 0x202,
+                frame: 0,
+            })
+        ));
+        assert_eq!(
+            outcome.unwrap().to_string(),
+            "frame 0, PC 0202: return with an empty stack"
+        );
+    }
+
+    #[test]
+    fn hard_reset_test() {
+        let path = std::env::temp_dir().join(format!("chip8-reset-test-{}", std::process::id()));
+        // LD V0, 3; JP 202
+        std::fs::write(&path, [0x60, 0x03, 0x12, 0x02]).unwrap();
+        let mut emulator = Emulator::headless();
+        emulator.load_rom(path.to_str().unwrap()).unwrap();
+        emulator.step();
+        emulator.set_key(0x4, true).unwrap();
+
+        emulator.hard_reset().unwrap();
+        assert_eq!((emulator.pc(), emulator.registers()[0]), (0x200, 0));
+        assert!(emulator.processor().keys_pressed[0x4]);
+
+        // Without the file the old machine is kept.
+        emulator.step();
+        std::fs::remove_file(&path).unwrap();
+        assert!(emulator.hard_reset().is_err());
+        assert_eq!((emulator.pc(), emulator.registers()[0]), (0x202, 3));
+        assert_eq!(emulator.memory()[0x200], 0x60);
+    }
+}
@@ -0,0 +1,1084 @@
+use crate::emulator::consts;
+use crate::emulator::processor::ProcessorError;
+use std::collections::{HashMap, VecDeque};
+
+// Registers Octo uses behind the scenes, which programs can re-alias.
+const UNPACK_HI: &str = "unpack-hi";
+const UNPACK_LO: &str = "unpack-lo";
+const COMPARE_TEMP: &str = "compare-temp";
+
+// Limits on macro expansion, so that a macro calling itself in an untrusted
+// cartridge fails instead of expanding forever.
+const MAX_MACRO_DEPTH: usize = 64;
+const MAX_EXPANDED_TOKENS: usize = 1 << 20;
+
+#[derive(Debug, Clone)]
+struct Token {
+    text: String,
+    line: usize,
+    // How many macro expansions deep the token came from.
+    depth: usize,
+}
+
+enum Value {
+    Known(i64),
+    Forward(String),
+}
+
+#[derive(Clone, Copy)]
+enum FixupKind {
+    // The low 12 bits of an instruction.
+    Address,
+    // The 16-bit word after `i := long`.
+    Long,
+    // The immediates of the two loads of `:unpack`, with the nibble that
+    // goes above the address or `None` for `:unpack long`.
+    Unpack(Option<u8>),
+}
+
+struct Fixup {
+    name: String,
+    addr: usize,
+    kind: FixupKind,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+    calls: usize,
+}
+
+// Assembles Octo source into a ROM loaded at ROM_START. Everything but
+// `:stringmode` is supported. As in Octo, programs start with a jump to
+// `main` unless it comes first.
+pub fn assemble(source: &str) -> Result<Vec<u8>, ProcessorError> {
+    let mut assembler = Assembler::new(source);
+    assembler.run().map_err(|err| {
+        ProcessorError::InvalidSource(format!("line {}: {}", assembler.line, err))
+    })?;
+
+    Ok(assembler.rom)
+}
+
+struct Assembler {
+    tokens: VecDeque<Token>,
+    line: usize,
+    depth: usize,
+    expanded: usize,
+    rom: Vec<u8>,
+    here: usize,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    // Jumps of `begin` and `else` waiting for their `else` or `end`.
+    branches: Vec<usize>,
+    // Start of every open `loop` with the jumps of its `while`s.
+    loops: Vec<(usize, Vec<usize>)>,
+}
+
+impl Assembler {
+    fn new(source: &str) -> Assembler {
+        let aliases = [(UNPACK_HI, 0x0), (UNPACK_LO, 0x1), (COMPARE_TEMP, 0xF)]
+            .into_iter()
+            .map(|(name, register)| (name.to_string(), register))
+            .collect();
+
+        Assembler {
+            tokens: tokenize(source),
+            line: 1,
+            depth: 0,
+            expanded: 0,
+            rom: Vec::new(),
+            here: consts::ROM_START,
+            constants: HashMap::new(),
+            aliases,
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            branches: Vec::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn run(&mut self) -> Result<(), String> {
+        self.fixup("main".to_string(), FixupKind::Address);
+        self.instruction(0x10, 0x00)?;
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if !self.branches.is_empty() {
+            return Err("'begin' without 'end'".to_string());
+        }
+        if !self.loops.is_empty() {
+            return Err("'loop' without 'again'".to_string());
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            self.line = fixup.line;
+            let value = match self.constants.get(&fixup.name) {
+                Some(value) => *value as i64,
+                None => return Err(format!("undefined name '{}'", fixup.name)),
+            };
+            let offset = fixup.addr - consts::ROM_START;
+            match fixup.kind {
+                FixupKind::Address => {
+                    let addr = address(value, 0xFFF)?;
+                    self.rom[offset] |= (addr >> 8) as u8;
+                    self.rom[offset + 1] = addr as u8;
+                }
+                FixupKind::Long => {
+                    let addr = address(value, 0xFFFF)?;
+                    self.rom[offset] = (addr >> 8) as u8;
+                    self.rom[offset + 1] = addr as u8;
+                }
+                FixupKind::Unpack(nibble) => {
+                    let (hi, lo) = unpack(nibble, value)?;
+                    self.rom[offset + 1] = hi;
+                    self.rom[offset + 3] = lo;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self.tokens.pop_front().ok_or("unexpected end of source")?;
+        self.line = token.line;
+        self.depth = token.depth;
+
+        Ok(token.text)
+    }
+
+    fn peek(&self, ahead: usize) -> Option<&str> {
+        self.tokens.get(ahead).map(|token| token.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        let token = self.next()?;
+        if token != expected {
+            return Err(format!("expected '{}', found '{}'", expected, token));
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), String> {
+        let token = self.next()?;
+
+        if let Some(register) = self.register_index(&token) {
+            return self.register_statement(register);
+        }
+        if self.macros.contains_key(&token) {
+            return self.expand(&token);
+        }
+        if let Some(number) = parse_number(&token) {
+            let byte = short(number as i64)?;
+            return self.emit(byte);
+        }
+
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                // Nothing but the jump to main so far, which can go.
+                if name == "main" && self.here == consts::ROM_START + 2 {
+                    self.rom.clear();
+                    self.fixups.clear();
+                    self.here = consts::ROM_START;
+                }
+                self.define(name, self.here as f64)
+            }
+            ":const" => {
+                let name = self.next()?;
+                let value = self.known_value()?;
+                self.define(name, value as f64)
+            }
+            ":calc" => {
+                let name = self.next()?;
+                let value = self.calc_block()?;
+                self.define(name, value)
+            }
+            ":alias" => {
+                let name = self.next()?;
+                let register = if self.peek(0) == Some("{") {
+                    let value = self.calc_block()? as i64;
+                    u8::try_from(value)
+                        .ok()
+                        .filter(|register| *register < 16)
+                        .ok_or(format!("no register v{}", value))?
+                } else {
+                    self.register()?
+                };
+                self.aliases.insert(name, register);
+                Ok(())
+            }
+            ":next" => {
+                let name = self.next()?;
+                self.define(name, (self.here + 1) as f64)
+            }
+            ":org" => {
+                let addr = self.known_value()?;
+                self.here = address(addr, 0xFFFF)?;
+                if self.here < consts::ROM_START {
+                    return Err(format!("cannot assemble below {:X}", consts::ROM_START));
+                }
+                Ok(())
+            }
+            ":unpack" => {
+                let nibble = if self.peek(0) == Some("long") {
+                    self.next()?;
+                    None
+                } else {
+                    let nibble = self.known_value()?;
+                    Some(
+                        u8::try_from(nibble)
+                            .ok()
+                            .filter(|nibble| *nibble < 16)
+                            .ok_or(format!("{} does not fit in a nibble", nibble))?,
+                    )
+                };
+                let (hi, lo) = (self.aliases[UNPACK_HI], self.aliases[UNPACK_LO]);
+                let (hi_value, lo_value) = match self.value()? {
+                    Value::Known(value) => unpack(nibble, value)?,
+                    Value::Forward(name) => {
+                        self.fixup(name, FixupKind::Unpack(nibble));
+                        (0, 0)
+                    }
+                };
+                self.instruction(0x60 | hi, hi_value)?;
+                self.instruction(0x60 | lo, lo_value)
+            }
+            ":byte" => {
+                let value = if self.peek(0) == Some("{") {
+                    self.calc_block()? as i64
+                } else {
+                    self.known_value()?
+                };
+                let byte = short(value)?;
+                self.emit(byte)
+            }
+            ":macro" => self.define_macro(),
+            ":assert" => {
+                let message = match self.peek(0) {
+                    Some(text) if text.starts_with('"') => Some(self.next()?),
+                    _ => None,
+                };
+                if self.calc_block()? == 0.0 {
+                    let message = message.unwrap_or_default();
+                    return Err(format!("assertion failed {}", message.trim_matches('"')));
+                }
+                Ok(())
+            }
+            ":call" => self.wide(0x20),
+            // Debugger hints have no effect on the program.
+            ":breakpoint" | ":proto" => self.next().map(|_| ()),
+            ":monitor" => {
+                self.next()?;
+                self.next().map(|_| ())
+            }
+            "clear" => self.instruction(0x00, 0xE0),
+            ";" | "return" => self.instruction(0x00, 0xEE),
+            "hires" => self.instruction(0x00, 0xFF),
+            "lores" => self.instruction(0x00, 0xFE),
+            "exit" => self.instruction(0x00, 0xFD),
+            "scroll-left" => self.instruction(0x00, 0xFC),
+            "scroll-right" => self.instruction(0x00, 0xFB),
+            "scroll-down" => {
+                let rows = self.tiny_value()?;
+                self.instruction(0x00, 0xC0 | rows)
+            }
+            "scroll-up" => {
+                let rows = self.tiny_value()?;
+                self.instruction(0x00, 0xD0 | rows)
+            }
+            "audio" => self.instruction(0xF0, 0x02),
+            "plane" => {
+                let plane = self.tiny_value()?;
+                if plane > 3 {
+                    return Err(format!("no plane {}", plane));
+                }
+                self.instruction(0xF0 | plane, 0x01)
+            }
+            "jump" => self.wide(0x10),
+            "jump0" => self.wide(0xB0),
+            "native" => self.wide(0x00),
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let height = self.tiny_value()?;
+                self.instruction(0xD0 | x, y << 4 | height)
+            }
+            "save" | "load" => {
+                let x = self.register()?;
+                if self.peek(0) == Some("-") {
+                    self.next()?;
+                    let y = self.register()?;
+                    let low = if token == "save" { 0x2 } else { 0x3 };
+                    return self.instruction(0x50 | x, y << 4 | low);
+                }
+                let low = if token == "save" { 0x55 } else { 0x65 };
+                self.instruction(0xF0 | x, low)
+            }
+            "bcd" => {
+                let x = self.register()?;
+                self.instruction(0xF0 | x, 0x33)
+            }
+            "saveflags" | "loadflags" => {
+                let x = self.register()?;
+                let low = if token == "saveflags" { 0x75 } else { 0x85 };
+                self.instruction(0xF0 | x, low)
+            }
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let low = match token.as_str() {
+                    "delay" => 0x15,
+                    "buzzer" => 0x18,
+                    _ => 0x3A,
+                };
+                self.instruction(0xF0 | x, low)
+            }
+            "i" => self.index_statement(),
+            "if" => self.if_statement(),
+            "else" => {
+                let branch = self.branches.pop().ok_or("'else' without 'begin'")?;
+                self.branches.push(self.here);
+                self.instruction(0x10, 0x00)?;
+                self.patch_jump(branch)
+            }
+            "end" => {
+                let branch = self.branches.pop().ok_or("'end' without 'begin'")?;
+                self.patch_jump(branch)
+            }
+            "loop" => {
+                self.loops.push((self.here, Vec::new()));
+                Ok(())
+            }
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err("'while' outside a loop".to_string());
+                }
+                self.condition(true)?;
+                let here = self.here;
+                if let Some((_, whiles)) = self.loops.last_mut() {
+                    whiles.push(here);
+                }
+                self.instruction(0x10, 0x00)
+            }
+            "again" => {
+                let (start, whiles) = self.loops.pop().ok_or("'again' without 'loop'")?;
+                let start = address(start as i64, 0xFFF)?;
+                self.instruction(0x10 | (start >> 8) as u8, start as u8)?;
+                for jump in whiles {
+                    self.patch_jump(jump)?;
+                }
+                Ok(())
+            }
+            ":stringmode" => Err("':stringmode' is not supported".to_string()),
+            _ if token.starts_with(':') || token.starts_with('"') => {
+                Err(format!("unexpected '{}'", token))
+            }
+            // Any other name calls the subroutine it labels.
+            _ => {
+                self.tokens.push_front(Token {
+                    text: token,
+                    line: self.line,
+                    depth: self.depth,
+                });
+                self.wide(0x20)
+            }
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), String> {
+        let op = self.next()?;
+
+        match op.as_str() {
+            ":=" => match self.peek(0) {
+                Some("random") => {
+                    self.next()?;
+                    let mask = self.short_value()?;
+                    self.instruction(0xC0 | x, mask)
+                }
+                Some("key") => {
+                    self.next()?;
+                    self.instruction(0xF0 | x, 0x0A)
+                }
+                Some("delay") => {
+                    self.next()?;
+                    self.instruction(0xF0 | x, 0x07)
+                }
+                _ if self.peek_register() => {
+                    let y = self.register()?;
+                    self.instruction(0x80 | x, y << 4)
+                }
+                _ => {
+                    let value = self.short_value()?;
+                    self.instruction(0x60 | x, value)
+                }
+            },
+            "+=" | "-=" if !self.peek_register() => {
+                let value = self.known_value()?;
+                let value = if op == "-=" { -value } else { value };
+                let byte = short(value)?;
+                self.instruction(0x70 | x, byte)
+            }
+            _ => {
+                let low = match op.as_str() {
+                    "|=" => 0x1,
+                    "&=" => 0x2,
+                    "^=" => 0x3,
+                    "+=" => 0x4,
+                    "-=" => 0x5,
+                    ">>=" => 0x6,
+                    "=-" => 0x7,
+                    "<<=" => 0xE,
+                    _ => return Err(format!("unknown operator '{}'", op)),
+                };
+                let y = self.register()?;
+                self.instruction(0x80 | x, y << 4 | low)
+            }
+        }
+    }
+
+    fn index_statement(&mut self) -> Result<(), String> {
+        let op = self.next()?;
+        match op.as_str() {
+            ":=" => match self.peek(0) {
+                Some("hex") | Some("bighex") => {
+                    let low = if self.next()? == "hex" { 0x29 } else { 0x30 };
+                    let x = self.register()?;
+                    self.instruction(0xF0 | x, low)
+                }
+                Some("long") => {
+                    self.next()?;
+                    self.instruction(0xF0, 0x00)?;
+                    match self.value()? {
+                        Value::Known(value) => {
+                            let addr = address(value, 0xFFFF)?;
+                            self.instruction((addr >> 8) as u8, addr as u8)
+                        }
+                        Value::Forward(name) => {
+                            self.fixup(name, FixupKind::Long);
+                            self.instruction(0x00, 0x00)
+                        }
+                    }
+                }
+                _ => self.wide(0xA0),
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.instruction(0xF0 | x, 0x1E)
+            }
+            _ => Err(format!("unknown operator 'i {}'", op)),
+        }
+    }
+
+    fn if_statement(&mut self) -> Result<(), String> {
+        let operands = match self.peek(1) {
+            Some("key") | Some("-key") => 2,
+            _ => 3,
+        };
+        match self.peek(operands) {
+            Some("then") => {
+                self.condition(false)?;
+                self.next().map(|_| ())
+            }
+            Some("begin") => {
+                self.condition(true)?;
+                self.next()?;
+                self.branches.push(self.here);
+                self.instruction(0x10, 0x00)
+            }
+            _ => Err("'if' without 'then' or 'begin'".to_string()),
+        }
+    }
+
+    // Emits a skip over the next instruction for when the condition doesn't
+    // hold, or when it does if `negated`.
+    fn condition(&mut self, negated: bool) -> Result<(), String> {
+        let x = self.register()?;
+        let mut op = self.next()?;
+        if negated {
+            op = match op.as_str() {
+                "==" => "!=",
+                "!=" => "==",
+                "key" => "-key",
+                "-key" => "key",
+                "<" => ">=",
+                ">" => "<=",
+                "<=" => ">",
+                ">=" => "<",
+                _ => return Err(format!("unknown comparison '{}'", op)),
+            }
+            .to_string();
+        }
+
+        match op.as_str() {
+            "==" | "!=" => {
+                let equal = op == "==";
+                if self.peek_register() {
+                    let y = self.register()?;
+                    self.instruction(if equal { 0x90 } else { 0x50 } | x, y << 4)
+                } else {
+                    let value = self.short_value()?;
+                    self.instruction(if equal { 0x40 } else { 0x30 } | x, value)
+                }
+            }
+            "key" => self.instruction(0xE0 | x, 0xA1),
+            "-key" => self.instruction(0xE0 | x, 0x9E),
+            ">" | "<" | ">=" | "<=" => {
+                // VF := operand, then subtract one from the other for the
+                // borrow flag.
+                let temp = self.aliases[COMPARE_TEMP];
+                if self.peek_register() {
+                    let y = self.register()?;
+                    self.instruction(0x80 | temp, y << 4)?;
+                } else {
+                    let value = self.short_value()?;
+                    self.instruction(0x60 | temp, value)?;
+                }
+                let (low, skip) = match op.as_str() {
+                    ">" => (0x5, 0x30),
+                    "<" => (0x7, 0x30),
+                    ">=" => (0x7, 0x40),
+                    _ => (0x5, 0x40),
+                };
+                self.instruction(0x80 | temp, x << 4 | low)?;
+                self.instruction(skip | temp, 0x01)
+            }
+            _ => Err(format!("unknown comparison '{}'", op)),
+        }
+    }
+
+    fn define_macro(&mut self) -> Result<(), String> {
+        let name = self.next()?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            args.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = self.tokens.pop_front().ok_or("unterminated macro")?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => depth -= 1,
+                _ => {}
+            }
+            if depth == 0 {
+                break;
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(
+            name,
+            Macro {
+                args,
+                body,
+                calls: 0,
+            },
+        );
+
+        Ok(())
+    }
+
+    // The body replaces the call, with its arguments substituted and CALLS
+    // standing for the number of earlier calls.
+    fn expand(&mut self, name: &str) -> Result<(), String> {
+        let depth = self.depth + 1;
+        if depth > MAX_MACRO_DEPTH {
+            return Err(format!(
+                "macros nested more than {} deep in '{}'",
+                MAX_MACRO_DEPTH, name
+            ));
+        }
+        let arg_count = self.macros[name].args.len();
+        let mut values = HashMap::new();
+        for i in 0..arg_count {
+            let value = self.next()?;
+            values.insert(self.macros[name].args[i].clone(), value);
+        }
+
+        let line = self.line;
+        let definition = self.macros.get_mut(name).ok_or("no macro")?;
+        values.insert("CALLS".to_string(), definition.calls.to_string());
+        definition.calls += 1;
+        self.expanded += definition.body.len();
+        if self.expanded > MAX_EXPANDED_TOKENS {
+            return Err(format!(
+                "macros expand to more than {} tokens in '{}'",
+                MAX_EXPANDED_TOKENS, name
+            ));
+        }
+
+        for token in definition.body.iter().rev() {
+            let text = values.get(&token.text).unwrap_or(&token.text);
+            self.tokens.push_front(Token {
+                text: text.clone(),
+                line,
+                depth,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn calc_block(&mut self) -> Result<f64, String> {
+        self.expect("{")?;
+        let value = self.calc_expression()?;
+        self.expect("}")?;
+
+        Ok(value)
+    }
+
+    // Octo evaluates binary operators right to left without precedence.
+    fn calc_expression(&mut self) -> Result<f64, String> {
+        let left = self.calc_term()?;
+        match self.peek(0) {
+            Some(")") | Some("}") | None => Ok(left),
+            _ => {
+                let op = self.next()?;
+                let right = self.calc_expression()?;
+                binary(&op, left, right)
+            }
+        }
+    }
+
+    fn calc_term(&mut self) -> Result<f64, String> {
+        let token = self.next()?;
+        if let Some(number) = parse_number(&token) {
+            return Ok(number);
+        }
+        if let Some(value) = self.constants.get(&token) {
+            return Ok(*value);
+        }
+
+        let value = match token.as_str() {
+            "(" => {
+                let value = self.calc_expression()?;
+                self.expect(")")?;
+                return Ok(value);
+            }
+            "HERE" => return Ok(self.here as f64),
+            "PI" => return Ok(std::f64::consts::PI),
+            "E" => return Ok(std::f64::consts::E),
+            _ => self.calc_term()?,
+        };
+
+        Ok(match token.as_str() {
+            "-" => -value,
+            "~" => !(value as i64) as f64,
+            "!" => (value == 0.0) as i64 as f64,
+            "@" => {
+                let offset = (value as usize).wrapping_sub(consts::ROM_START);
+                self.rom.get(offset).copied().unwrap_or(0) as f64
+            }
+            "abs" => value.abs(),
+            "sqrt" => value.sqrt(),
+            "floor" => value.floor(),
+            "ceil" => value.ceil(),
+            "sign" => value.signum(),
+            "sin" => value.sin(),
+            "cos" => value.cos(),
+            "tan" => value.tan(),
+            "exp" => value.exp(),
+            "log" => value.ln(),
+            _ => return Err(format!("undefined name '{}'", token)),
+        })
+    }
+
+    fn define(&mut self, name: String, value: f64) -> Result<(), String> {
+        if self.register_index(&name).is_some() || parse_number(&name).is_some() {
+            return Err(format!("'{}' cannot be redefined", name));
+        }
+        self.constants.insert(name, value);
+
+        Ok(())
+    }
+
+    fn register_index(&self, token: &str) -> Option<u8> {
+        if let Some(register) = self.aliases.get(token) {
+            return Some(*register);
+        }
+
+        let digit = token.strip_prefix(['v', 'V'])?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    fn peek_register(&self) -> bool {
+        self.peek(0)
+            .is_some_and(|token| self.register_index(token).is_some())
+    }
+
+    fn register(&mut self) -> Result<u8, String> {
+        let token = self.next()?;
+        self.register_index(&token)
+            .ok_or(format!("expected a register, found '{}'", token))
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let token = self.next()?;
+        if let Some(number) = parse_number(&token) {
+            return Ok(Value::Known(number as i64));
+        }
+        if let Some(value) = self.constants.get(&token) {
+            return Ok(Value::Known(*value as i64));
+        }
+        if self.register_index(&token).is_some() {
+            return Err(format!("expected a value, found '{}'", token));
+        }
+
+        Ok(Value::Forward(token))
+    }
+
+    fn known_value(&mut self) -> Result<i64, String> {
+        match self.value()? {
+            Value::Known(value) => Ok(value),
+            Value::Forward(name) => Err(format!("undefined name '{}'", name)),
+        }
+    }
+
+    fn short_value(&mut self) -> Result<u8, String> {
+        let value = self.known_value()?;
+        short(value)
+    }
+
+    fn tiny_value(&mut self) -> Result<u8, String> {
+        let value = self.known_value()?;
+        u8::try_from(value)
+            .ok()
+            .filter(|value| *value < 16)
+            .ok_or(format!("{} does not fit in a nibble", value))
+    }
+
+    // An instruction whose low 12 bits are an address, which may be defined
+    // further down.
+    fn wide(&mut self, high: u8) -> Result<(), String> {
+        match self.value()? {
+            Value::Known(value) => {
+                let addr = address(value, 0xFFF)?;
+                self.instruction(high | (addr >> 8) as u8, addr as u8)
+            }
+            Value::Forward(name) => {
+                self.fixup(name, FixupKind::Address);
+                self.instruction(high, 0x00)
+            }
+        }
+    }
+
+    fn fixup(&mut self, name: String, kind: FixupKind) {
+        self.fixups.push(Fixup {
+            name,
+            addr: self.here,
+            kind,
+            line: self.line,
+        });
+    }
+
+    // Points the jump at `addr` to here.
+    fn patch_jump(&mut self, addr: usize) -> Result<(), String> {
+        let here = address(self.here as i64, 0xFFF)?;
+        let offset = addr - consts::ROM_START;
+        self.rom[offset] = 0x10 | (here >> 8) as u8;
+        self.rom[offset + 1] = here as u8;
+
+        Ok(())
+    }
+
+    fn instruction(&mut self, high: u8, low: u8) -> Result<(), String> {
+        self.emit(high)?;
+        self.emit(low)
+    }
+
+    fn emit(&mut self, byte: u8) -> Result<(), String> {
+        if self.here >= consts::XO_MEMORY_SIZE {
+            return Err("program does not fit in memory".to_string());
+        }
+
+        let offset = self.here - consts::ROM_START;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+
+        Ok(())
+    }
+}
+
+// Words are split on whitespace, `#` starts a comment and double quotes keep
+// a string together.
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+
+            let mut text = String::new();
+            if c == '"' {
+                text.push(c);
+                chars.next();
+                for c in chars.by_ref() {
+                    text.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+            } else {
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() {
+                        break;
+                    }
+                    text.push(c);
+                    chars.next();
+                }
+            }
+            tokens.push_back(Token {
+                text,
+                line: index + 1,
+                depth: 0,
+            });
+        }
+    }
+
+    tokens
+}
+
+fn parse_number(token: &str) -> Option<f64> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+fn binary(op: &str, left: f64, right: f64) -> Result<f64, String> {
+    let (a, b) = (left as i64, right as i64);
+    Ok(match op {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.checked_shl(b as u32).unwrap_or(0) as f64,
+        ">>" => a.checked_shr(b as u32).unwrap_or(0) as f64,
+        "<" => (left < right) as i64 as f64,
+        ">" => (left > right) as i64 as f64,
+        "<=" => (left <= right) as i64 as f64,
+        ">=" => (left >= right) as i64 as f64,
+        "==" => (left == right) as i64 as f64,
+        "!=" => (left != right) as i64 as f64,
+        _ => return Err(format!("unknown operator '{}'", op)),
+    })
+}
+
+// Bytes may be given signed.
+fn short(value: i64) -> Result<u8, String> {
+    match value {
+        -128..=255 => Ok(value as u8),
+        _ => Err(format!("{} does not fit in a byte", value)),
+    }
+}
+
+fn address(value: i64, max: usize) -> Result<usize, String> {
+    usize::try_from(value)
+        .ok()
+        .filter(|addr| *addr <= max)
+        .ok_or(format!("address {:X} is out of range", value))
+}
+
+fn unpack(nibble: Option<u8>, value: i64) -> Result<(u8, u8), String> {
+    let max = if nibble.is_some() { 0xFFF } else { 0xFFFF };
+    let addr = address(value, max)?;
+    let hi = match nibble {
+        Some(nibble) => nibble << 4 | (addr >> 8) as u8,
+        None => (addr >> 8) as u8,
+    };
+
+    Ok((hi, addr as u8))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::emulator::processor::Processor;
+
+    #[test]
+    fn assemble_test() {
+        let rom = assemble(
+            "
+            :const SPEED 3
+            :alias x v4
+            : main
+                clear
+                i := sprite
+                x := 0
+                loop
+                    sprite x v5 5
+                    x += SPEED
+                    if x key then x := 1
+                    if x > 60 begin
+                        x := 0
+                    else
+                        draw
+                    end
+                    while x != 9
+                again
+                exit
+            : draw
+                v0 := random 0x0F ;
+            : sprite 0xF0 0x90 -1
+            ",
+        )
+        .unwrap();
+
+        let words: Vec<u16> = rom
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], *word.get(1).unwrap_or(&0)]))
+            .collect();
+        assert_eq!(
+            words,
+            vec![
+                0x00E0, 0xA228, 0x6400, // main
+                0xD455, 0x7403, // loop
+                0xE4A1, 0x6401, // if key then
+                0x6F3C, 0x8F45, 0x4F01, 0x121A, 0x6400, 0x121C, 0x2224, // if > begin
+                0x4409, 0x1222, // while
+                0x1206, 0x00FD, // again
+                0xC00F, 0x00EE, // draw
+                0xF090, 0xFF00,
+            ]
+        );
+    }
+
+    #[test]
+    fn forward_references_test() {
+        let rom = assemble(
+            "
+            :macro load-address reg label { :unpack long label reg := v1 }
+            :calc SIZE { 2 * 1 + 1 }
+            main
+            : main
+                i := long data
+                load-address v2 data
+                jump main
+            : data :byte { SIZE } :byte { @ data }
+            ",
+        )
+        .unwrap();
+
+        // No `: main` in front, so a jump to it comes first.
+        assert_eq!(
+            rom,
+            vec![
+                0x12, 0x04, // jump main
+                0x22, 0x04, // main
+                0xF0, 0x00, 0x02, 0x10, // i := long data
+                0x60, 0x02, 0x61, 0x10, 0x82, 0x10, // load-address
+                0x12, 0x04, // jump main
+                0x04, 0x04, // data
+            ]
+        );
+
+        assert!(matches!(
+            assemble(": main jump nowhere"),
+            Err(ProcessorError::InvalidSource(_))
+        ));
+        assert!(matches!(
+            assemble(": main v0 := 256"),
+            Err(ProcessorError::InvalidSource(_))
+        ));
+    }
+
+    #[test]
+    fn runaway_macros_test() {
+        assert!(matches!(
+            assemble(":macro forever { forever } : main forever"),
+            Err(ProcessorError::InvalidSource(_))
+        ));
+        // Wide instead of deep, and emitting nothing that would run out of
+        // memory first: 16^6 `:const`s.
+        let source = ":macro m0 { :const A 1 }\n".to_string()
+            + &(1..=6)
+                .map(|i| {
+                    format!(
+                        ":macro m{} {{ {} }}\n",
+                        i,
+                        format!("m{} ", i - 1).repeat(16)
+                    )
+                })
+                .collect::<String>()
+            + ": main m6";
+        let err = assemble(&source).unwrap_err().to_string();
+        assert!(err.contains("more than 1048576 tokens"), "{}", err);
+        assert!(assemble(":macro twice { clear clear } : main twice twice").is_ok());
+    }
+
+    #[test]
+    fn comparisons_test() {
+        let rom = assemble(
+            "
+            : main
+                v0 := 5
+                v5 := 6
+                if v0 > 3 then v1 := 1
+                if v0 < 3 then v2 := 1
+                if v0 >= 5 then v3 := 1
+                if v0 <= 4 then v4 := 1
+                if v0 < v5 then v6 := 1
+                v8 := 1
+                if v8 > 3 then v7 := 1
+                if v8 <= 1 then v9 := 1
+            : end
+                jump end
+            ",
+        )
+        .unwrap();
+
+        let mut processor = Processor::new();
+        processor.load_rom_bytes("test", &rom).unwrap();
+        for _ in 0..64 {
+            processor.emulate_cycle().unwrap();
+        }
+        assert_eq!(processor.registers()[1..10], [1, 0, 1, 0, 6, 1, 0, 1, 1]);
+    }
+}
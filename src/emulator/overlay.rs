@@ -0,0 +1,522 @@
+use crate::emulator::consts;
+use crate::emulator::font;
+use crate::emulator::frame::Frame;
+use crate::emulator::memory_view::{self, ByteKind, MemoryView};
+use crate::emulator::processor::Processor;
+use crate::emulator::sprites;
+use std::collections::BTreeMap;
+
+pub const PANELS: [&str; 7] = [
+    "registers",
+    "stack",
+    "disassembly",
+    "memory",
+    "sprites",
+    "keypad",
+    "counters",
+];
+
+pub const OVERLAY_WIDTH: usize = 128;
+
+const CELL_WIDTH: usize = font::GLYPH_WIDTH + 1;
+const LINE_HEIGHT: usize = font::GLYPH_HEIGHT + 2;
+const MARGIN: usize = 3;
+// Instructions shown before and after the one at PC.
+const DISASSEMBLY_CONTEXT: usize = 3;
+// Bytes the sprites panel shows, starting where the memory view does.
+const SPRITE_PANEL_BYTES: usize = 64;
+
+const BACKGROUND: [u8; 3] = [24, 24, 24];
+const TEXT: [u8; 3] = [220, 220, 220];
+const HEADING: [u8; 3] = [255, 196, 64];
+const HIGHLIGHT: [u8; 3] = [64, 160, 255];
+const DIM: [u8; 3] = [90, 90, 90];
+const INDEX: [u8; 3] = [96, 224, 96];
+const WRITTEN: [u8; 3] = [255, 96, 64];
+const CURSOR: [u8; 3] = [255, 80, 255];
+
+// Runs of text, each in its own colour.
+type Line = Vec<(String, [u8; 3])>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Panel {
+    // V0-VF, I, PC, SP and both timers.
+    Registers,
+    Stack,
+    Disassembly,
+    // Hex, ASCII and sprite view of memory with an editing cursor.
+    Memory,
+    // The memory view's bytes as sprites of the current DRW's height.
+    Sprites,
+    Keypad,
+    // Frames and instructions per second.
+    Counters,
+}
+
+impl Panel {
+    pub fn parse(name: &str) -> Option<Panel> {
+        match name {
+            "registers" => Some(Panel::Registers),
+            "stack" => Some(Panel::Stack),
+            "disassembly" => Some(Panel::Disassembly),
+            "memory" => Some(Panel::Memory),
+            "sprites" => Some(Panel::Sprites),
+            "keypad" => Some(Panel::Keypad),
+            "counters" => Some(Panel::Counters),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Counters {
+    pub fps: u32,
+    pub ips: u32,
+}
+
+// Shown above the panels while controller buttons are being mapped: the
+// CHIP-8 key waiting for a button and the buttons of every key, as
+// `Keymap::buttons_by_key` gives them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ButtonPrompt {
+    pub key: usize,
+    pub buttons: BTreeMap<String, Vec<String>>,
+}
+
+// Debug panels drawn next to the game view, in a bitmap font so no font
+// library is needed.
+#[derive(Debug)]
+pub struct Overlay {
+    visible: bool,
+    panels: Vec<Panel>,
+    memory_view: MemoryView,
+    button_prompt: Option<ButtonPrompt>,
+}
+
+impl Default for Overlay {
+    fn default() -> Overlay {
+        Overlay::new()
+    }
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay {
+            visible: false,
+            panels: PANELS
+                .iter()
+                .filter_map(|name| Panel::parse(name))
+                .collect(),
+            memory_view: MemoryView::new(),
+            button_prompt: None,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.stop_hidden_editing();
+    }
+
+    pub fn shows(&self, panel: Panel) -> bool {
+        self.visible && self.panels.contains(&panel)
+    }
+
+    // Memory can only be edited while the memory panel is on screen.
+    pub fn toggle_memory_editing(&mut self) {
+        if self.shows(Panel::Memory) {
+            self.memory_view.toggle_editing();
+        }
+    }
+
+    fn stop_hidden_editing(&mut self) {
+        if self.memory_view.is_editing() && !self.shows(Panel::Memory) {
+            self.memory_view.toggle_editing();
+        }
+    }
+
+    pub fn memory_view(&self) -> &MemoryView {
+        &self.memory_view
+    }
+
+    pub fn memory_view_mut(&mut self) -> &mut MemoryView {
+        &mut self.memory_view
+    }
+
+    pub fn set_button_prompt(&mut self, prompt: Option<ButtonPrompt>) {
+        self.button_prompt = prompt;
+    }
+
+    pub fn set_panels(&mut self, panels: Vec<Panel>) {
+        self.panels = panels;
+        self.stop_hidden_editing();
+    }
+
+    // Panels keep the order of PANELS whichever way they are switched on.
+    pub fn toggle_panel(&mut self, panel: Panel) {
+        if self.panels.contains(&panel) {
+            self.panels.retain(|shown| *shown != panel);
+        } else {
+            self.panels.push(panel);
+            self.panels.sort_by_key(|shown| {
+                PANELS
+                    .iter()
+                    .position(|name| Panel::parse(name) == Some(*shown))
+            });
+        }
+        self.stop_hidden_editing();
+    }
+
+    pub fn render(&self, processor: &Processor, counters: Counters) -> Frame {
+        let mut lines: Vec<Line> = Vec::new();
+        let mut sprite_row = None;
+        let mut sprite_sheet = None;
+        if let Some(prompt) = &self.button_prompt {
+            button_prompt(prompt, &mut lines);
+        }
+        for panel in &self.panels {
+            if !lines.is_empty() {
+                lines.push(Vec::new());
+            }
+            match panel {
+                Panel::Registers => registers(processor, &mut lines),
+                Panel::Stack => stack(processor, &mut lines),
+                Panel::Disassembly => disassembly(processor, &mut lines),
+                Panel::Memory => {
+                    memory(processor, &self.memory_view, &mut lines);
+                    sprite_row = Some(lines.len() - memory_view::VIEW_ROWS);
+                }
+                Panel::Sprites => {
+                    let sheet = sprite_panel(processor, &self.memory_view, &mut lines);
+                    let row = lines.len();
+                    lines.resize(row + sheet.height.div_ceil(LINE_HEIGHT), Vec::new());
+                    sprite_sheet = Some((row, sheet));
+                }
+                Panel::Keypad => keypad(processor, &mut lines),
+                Panel::Counters => lines.push(vec![(
+                    format!("FPS {}  IPS {}", counters.fps, counters.ips),
+                    TEXT,
+                )]),
+            }
+        }
+
+        let height = (lines.len() * LINE_HEIGHT + 2 * MARGIN).max(consts::DISPLAY_HEIGHT);
+        let mut frame = Frame::new(OVERLAY_WIDTH, height);
+        frame.pixels.fill(BACKGROUND);
+        for (row, spans) in lines.iter().enumerate() {
+            let mut x = MARGIN;
+            for (text, color) in spans {
+                draw_text(&mut frame, x, MARGIN + row * LINE_HEIGHT, text, *color);
+                x += text.chars().count() * CELL_WIDTH;
+            }
+        }
+        if let Some(row) = sprite_row {
+            draw_sprite(
+                &mut frame,
+                MARGIN + row * LINE_HEIGHT,
+                processor,
+                &self.memory_view,
+            );
+        }
+        if let Some((row, sheet)) = sprite_sheet {
+            let y = MARGIN + row * LINE_HEIGHT;
+            for dy in 0..sheet.height.min(frame.height - y) {
+                for dx in 0..sheet.width.min(OVERLAY_WIDTH - MARGIN) {
+                    frame.set(MARGIN + dx, y + dy, sheet.get(dx, dy));
+                }
+            }
+        }
+
+        frame
+    }
+}
+
+fn button_prompt(prompt: &ButtonPrompt, lines: &mut Vec<Line>) {
+    lines.push(vec![("MAP CONTROLLER".to_string(), HEADING)]);
+    lines.push(vec![(
+        format!("PRESS A BUTTON FOR {:X}", prompt.key),
+        HIGHLIGHT,
+    )]);
+    lines.push(vec![("F8 DONE, SHIFT F8 SKIPS".to_string(), DIM)]);
+    for key in 0..consts::N_KEYS {
+        let color = if key == prompt.key { HIGHLIGHT } else { TEXT };
+        let buttons = match prompt.buttons.get(&format!("{:X}", key)) {
+            Some(buttons) => buttons.join(" "),
+            None => "-".to_string(),
+        };
+        lines.push(vec![(format!("{:X} {}", key, buttons), color)]);
+    }
+}
+
+fn registers(processor: &Processor, lines: &mut Vec<Line>) {
+    lines.push(vec![("REGISTERS".to_string(), HEADING)]);
+    for (row, values) in processor.registers().chunks(4).enumerate() {
+        let text = values
+            .iter()
+            .enumerate()
+            .map(|(column, value)| format!("V{:X} {:02X}", row * 4 + column, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+        lines.push(vec![(text, TEXT)]);
+    }
+    lines.push(vec![(
+        format!(
+            "I {:04X} PC {:04X} SP {:X}",
+            processor.index(),
+            processor.pc(),
+            processor.stack().len()
+        ),
+        TEXT,
+    )]);
+    lines.push(vec![(
+        format!(
+            "DT {:02X} ST {:02X}",
+            processor.delay_timer(),
+            processor.sound_timer()
+        ),
+        TEXT,
+    )]);
+}
+
+fn stack(processor: &Processor, lines: &mut Vec<Line>) {
+    lines.push(vec![("STACK".to_string(), HEADING)]);
+    let stack = processor.stack();
+    for row in 0..consts::STACK_SIZE / 4 {
+        let spans = (row * 4..row * 4 + 4)
+            .map(|level| match stack.get(level) {
+                Some(addr) => (format!("{:04X} ", addr), TEXT),
+                None => ("---- ".to_string(), DIM),
+            })
+            .collect();
+        lines.push(spans);
+    }
+}
+
+fn disassembly(processor: &Processor, lines: &mut Vec<Line>) {
+    lines.push(vec![("DISASSEMBLY".to_string(), HEADING)]);
+    let memory = processor.memory();
+    let pc = processor.pc() as usize;
+    let context = DISASSEMBLY_CONTEXT * 2;
+
+    for addr in (pc.saturating_sub(context)..=pc + context).step_by(2) {
+        if addr + 1 >= memory.len() {
+            break;
+        }
+        let opcode = u16::from_be_bytes([memory[addr], memory[addr + 1]]);
+        let (marker, color) = if addr == pc {
+            (">", HIGHLIGHT)
+        } else {
+            (" ", TEXT)
+        };
+        lines.push(vec![(
+            format!(
+                "{}{:04X} {:04X} {}",
+                marker,
+                addr,
+                opcode,
+                Processor::decode_instruction(opcode)
+            ),
+            color,
+        )]);
+    }
+}
+
+fn memory(processor: &Processor, view: &MemoryView, lines: &mut Vec<Line>) {
+    let heading = if view.is_editing() {
+        format!("MEMORY {:04X} EDIT", view.cursor())
+    } else {
+        "MEMORY".to_string()
+    };
+    lines.push(vec![(heading, HEADING)]);
+
+    let memory = processor.memory();
+    for row in 0..memory_view::VIEW_ROWS {
+        let addr = view.start() as usize + row * memory_view::BYTES_PER_ROW;
+        let bytes = &memory[addr..addr + memory_view::BYTES_PER_ROW];
+
+        let mut spans = vec![(format!("{:04X} ", addr), DIM)];
+        for (i, byte) in bytes.iter().enumerate() {
+            let color = match view.byte_kind(processor, (addr + i) as u16) {
+                ByteKind::Normal => TEXT,
+                ByteKind::Pc => HIGHLIGHT,
+                ByteKind::Index => INDEX,
+                ByteKind::RecentlyWritten => WRITTEN,
+                ByteKind::Cursor => CURSOR,
+            };
+            spans.push((format!("{:02X} ", byte), color));
+        }
+        spans.push((
+            bytes.iter().map(|byte| memory_view::ascii(*byte)).collect(),
+            DIM,
+        ));
+        lines.push(spans);
+    }
+}
+
+// The bytes on screen as a sprite, one byte per pixel row, right of the hex.
+fn draw_sprite(frame: &mut Frame, y: usize, processor: &Processor, view: &MemoryView) {
+    let x = OVERLAY_WIDTH - MARGIN - 8;
+    let start = view.start() as usize;
+    let bytes =
+        &processor.memory()[start..start + memory_view::VIEW_ROWS * memory_view::BYTES_PER_ROW];
+
+    for (dy, byte) in bytes.iter().enumerate() {
+        for dx in 0..8 {
+            let color = if byte & (0x80 >> dx) != 0 { TEXT } else { DIM };
+            frame.set(x + dx, y + dy, color);
+        }
+    }
+}
+
+// Sprites as tall as the current DRW's, or 8 rows before the first one, with
+// the bytes that DRW reads highlighted.
+fn sprite_panel(processor: &Processor, view: &MemoryView, lines: &mut Vec<Line>) -> Frame {
+    let draw = sprites::current_draw(processor);
+    let height = draw.map_or(8, |draw| draw.height);
+    let heading = match draw {
+        Some(draw) => format!("SPRITES DRW {:04X}:{}", draw.addr, draw.height),
+        None => "SPRITES".to_string(),
+    };
+    lines.push(vec![(heading, HEADING)]);
+
+    let (width, _) = sprites::sprite_size(height);
+    let columns = (OVERLAY_WIDTH - 2 * MARGIN) / (width + 1);
+    sprites::render_region(
+        processor.memory(),
+        view.start(),
+        SPRITE_PANEL_BYTES,
+        height,
+        columns,
+        draw,
+    )
+}
+
+// Keys in their physical COSMAC VIP layout, pressed ones highlighted.
+fn keypad(processor: &Processor, lines: &mut Vec<Line>) {
+    const LAYOUT: [[usize; 4]; 4] = [
+        [0x1, 0x2, 0x3, 0xC],
+        [0x4, 0x5, 0x6, 0xD],
+        [0x7, 0x8, 0x9, 0xE],
+        [0xA, 0x0, 0xB, 0xF],
+    ];
+
+    lines.push(vec![("KEYPAD".to_string(), HEADING)]);
+    for row in LAYOUT {
+        let spans = row
+            .iter()
+            .map(|key| {
+                let color = if processor.keys_pressed[*key] {
+                    HIGHLIGHT
+                } else {
+                    DIM
+                };
+                (format!("{:X} ", key), color)
+            })
+            .collect();
+        lines.push(spans);
+    }
+}
+
+fn draw_text(frame: &mut Frame, x: usize, y: usize, text: &str, color: [u8; 3]) {
+    for (i, c) in text.chars().enumerate() {
+        for (dy, row) in font::glyph(c).iter().enumerate() {
+            for dx in 0..font::GLYPH_WIDTH {
+                let (px, py) = (x + i * CELL_WIDTH + dx, y + dy);
+                if row & (0x80 >> dx) != 0 && px < frame.width && py < frame.height {
+                    frame.set(px, py, color);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn overlay_test() {
+        let mut processor = Processor::new();
+        processor
+            .load_rom_bytes("test", &[0x60, 0x05, 0x12, 0x00])
+            .unwrap();
+        processor.keys_pressed[0x5] = true;
+
+        let mut overlay = Overlay::new();
+        let frame = overlay.render(&processor, Counters { fps: 60, ips: 720 });
+        assert_eq!(frame.width, OVERLAY_WIDTH);
+        assert!(frame.pixels.contains(&HEADING));
+        assert!(frame.pixels.contains(&HIGHLIGHT));
+
+        overlay.toggle_panel(Panel::Registers);
+        overlay.toggle_panel(Panel::Stack);
+        overlay.toggle_panel(Panel::Disassembly);
+        overlay.toggle_panel(Panel::Memory);
+        overlay.toggle_panel(Panel::Sprites);
+        overlay.toggle_panel(Panel::Keypad);
+        let counters_only = overlay.render(&processor, Counters::default());
+        assert!(counters_only.height < frame.height);
+        assert!(!counters_only.pixels.contains(&HIGHLIGHT));
+
+        overlay.toggle_panel(Panel::Registers);
+        assert_eq!(overlay.panels, vec![Panel::Registers, Panel::Counters]);
+
+        // No editing while the memory panel is out of sight.
+        overlay.toggle_memory_editing();
+        assert!(!overlay.memory_view().is_editing());
+        overlay.toggle_panel(Panel::Memory);
+        overlay.toggle();
+        overlay.toggle_memory_editing();
+        assert!(overlay.memory_view().is_editing());
+        overlay.toggle();
+        assert!(!overlay.memory_view().is_editing());
+        overlay.toggle();
+        overlay.toggle_panel(Panel::Memory);
+
+        let unprompted = overlay.render(&processor, Counters::default());
+        overlay.set_button_prompt(Some(ButtonPrompt {
+            key: 0x5,
+            buttons: BTreeMap::from([("5".to_string(), vec!["dpup".to_string()])]),
+        }));
+        let prompted = overlay.render(&processor, Counters::default());
+        assert_eq!(
+            prompted.height,
+            unprompted.height + (3 + consts::N_KEYS + 1) * LINE_HEIGHT
+        );
+    }
+
+    #[test]
+    fn disassembly_test() {
+        let mut processor = Processor::new();
+        processor
+            .load_rom_bytes("test", &[0x60, 0x05, 0xD0, 0x15])
+            .unwrap();
+
+        let mut lines = Vec::new();
+        disassembly(&processor, &mut lines);
+        let text = lines
+            .iter()
+            .map(|spans| spans[0].0.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(text[4], ">0200 6005 LD V0, 05");
+        assert_eq!(text[5], " 0202 D015 DRW V0, V1, 5");
+
+        // The listing stops at the end of memory.
+        processor.set_pc(0xFFFC);
+        let mut lines = Vec::new();
+        disassembly(&processor, &mut lines);
+        assert_eq!(lines.len(), 6);
+        assert!(lines[4][0].0.starts_with(">FFFC"));
+    }
+
+    #[test]
+    fn font_test() {
+        assert_eq!(font::glyph('a'), [0xF0, 0x90, 0xF0, 0x90, 0x90]);
+        assert_eq!(font::glyph('P'), [0xE0, 0x90, 0xE0, 0x80, 0x80]);
+        assert_eq!(font::glyph('~'), [0; font::GLYPH_HEIGHT]);
+    }
+}
@@ -0,0 +1,93 @@
+use crate::emulator::rom_db;
+
+pub const THEMES: [&str; 5] = ["classic", "amber", "green", "gameboy", "octo"];
+
+// Entry 0 is the background. Two entries cover CHIP-8, four cover the XO-CHIP
+// plane combinations and sixteen are accepted for 4-plane variants.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::theme(THEMES[0]).unwrap()
+    }
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Option<Palette> {
+        match colors.len() {
+            2 | 4 | 16 => Some(Palette { colors }),
+            _ => None,
+        }
+    }
+
+    pub fn parse(colors: &str) -> Option<Palette> {
+        let colors = colors
+            .split(',')
+            .map(|color| rom_db::parse_color(color.trim()))
+            .collect::<Option<Vec<_>>>()?;
+
+        Palette::new(colors)
+    }
+
+    pub fn theme(name: &str) -> Option<Palette> {
+        let colors = match name {
+            "classic" => ["#000000", "#ffffff", "#aaaaaa", "#555555"],
+            "amber" => ["#1a1000", "#ffb000", "#cc7a00", "#ffd480"],
+            "green" => ["#001a00", "#33ff33", "#1f9f1f", "#aaffaa"],
+            "gameboy" => ["#9bbc0f", "#0f380f", "#306230", "#8bac0f"],
+            "octo" => ["#996600", "#ffcc00", "#ff6600", "#662200"],
+            _ => return None,
+        };
+
+        Palette::parse(&colors.join(","))
+    }
+
+    // The theme after this palette's, or the first one if the palette isn't
+    // a theme, such as ROM colours or --palette.
+    pub fn next_theme(&self) -> Palette {
+        let next = THEMES
+            .iter()
+            .position(|name| Palette::theme(name).as_ref() == Some(self))
+            .map_or(0, |theme| (theme + 1) % THEMES.len());
+
+        Palette::theme(THEMES[next]).unwrap()
+    }
+
+    pub fn color(&self, pixel: u8) -> [u8; 3] {
+        self.colors[pixel as usize % self.colors.len()]
+    }
+
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn palette_test() {
+        for theme in THEMES {
+            assert_eq!(Palette::theme(theme).unwrap().colors().len(), 4);
+        }
+
+        let palette = Palette::parse("#000000, #ff8000").unwrap();
+        assert_eq!(palette.color(0), [0, 0, 0]);
+        assert_eq!(palette.color(1), [0xff, 0x80, 0x00]);
+        assert_eq!(palette.color(3), [0xff, 0x80, 0x00]);
+
+        assert_eq!(palette.next_theme(), Palette::theme("classic").unwrap());
+        let amber = Palette::theme("amber").unwrap();
+        assert_eq!(amber.next_theme(), Palette::theme("green").unwrap());
+        let octo = Palette::theme("octo").unwrap();
+        assert_eq!(octo.next_theme(), Palette::theme("classic").unwrap());
+
+        assert_eq!(Palette::parse("#000000,#111111,#222222"), None);
+        assert_eq!(Palette::parse("#000000,red"), None);
+    }
+}
@@ -0,0 +1,182 @@
+use crate::emulator::consts;
+use crate::emulator::frame::Frame;
+use crate::emulator::palette::Palette;
+
+pub const DEFAULT_FADE_MS: u32 = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PersistenceMode {
+    Off,
+    // Averages each frame with the one before it.
+    Blend,
+    // Lit pixels light up at once and fade back over the given milliseconds.
+    Decay(u32),
+    // ORs the framebuffer with the previous one before colouring it.
+    AntiFlicker,
+}
+
+impl PersistenceMode {
+    pub fn parse(mode: &str) -> Option<PersistenceMode> {
+        match mode.split_once(':') {
+            Some(("decay", fade)) => Some(PersistenceMode::Decay(fade.parse().ok()?)),
+            Some(_) => None,
+            None => match mode {
+                "off" => Some(PersistenceMode::Off),
+                "blend" => Some(PersistenceMode::Blend),
+                "decay" => Some(PersistenceMode::Decay(DEFAULT_FADE_MS)),
+                "or" => Some(PersistenceMode::AntiFlicker),
+                _ => None,
+            },
+        }
+    }
+}
+
+pub struct Persistence {
+    mode: PersistenceMode,
+    previous: Vec<u8>,
+    previous_frame: Option<Frame>,
+    glow: Vec<[f32; 3]>,
+}
+
+impl Persistence {
+    pub fn new(mode: PersistenceMode) -> Persistence {
+        Persistence {
+            mode,
+            previous: Vec::new(),
+            previous_frame: None,
+            glow: Vec::new(),
+        }
+    }
+
+    pub fn mode(&self) -> PersistenceMode {
+        self.mode
+    }
+
+    // Every mode but Off depends on earlier frames, so the output can change
+    // even when the framebuffer did not.
+    pub fn is_active(&self) -> bool {
+        self.mode != PersistenceMode::Off
+    }
+
+    pub fn apply(
+        &mut self,
+        pixels: &[u8],
+        width: usize,
+        height: usize,
+        palette: &Palette,
+    ) -> Frame {
+        match self.mode {
+            PersistenceMode::Off => Frame::from_indices(pixels, width, height, palette),
+            PersistenceMode::AntiFlicker => {
+                let combined: Vec<u8> = if self.previous.len() == pixels.len() {
+                    pixels
+                        .iter()
+                        .zip(&self.previous)
+                        .map(|(current, previous)| current | previous)
+                        .collect()
+                } else {
+                    pixels.to_vec()
+                };
+                self.previous = pixels.to_vec();
+
+                Frame::from_indices(&combined, width, height, palette)
+            }
+            PersistenceMode::Blend => {
+                let current = Frame::from_indices(pixels, width, height, palette);
+                let mut blended = current.clone();
+                if let Some(previous) = self
+                    .previous_frame
+                    .as_ref()
+                    .filter(|p| p.pixels.len() == pixels.len())
+                {
+                    for (pixel, previous) in blended.pixels.iter_mut().zip(&previous.pixels) {
+                        for channel in 0..3 {
+                            pixel[channel] =
+                                ((pixel[channel] as u16 + previous[channel] as u16) / 2) as u8;
+                        }
+                    }
+                }
+                self.previous_frame = Some(current);
+
+                blended
+            }
+            PersistenceMode::Decay(fade_ms) => {
+                let step = if fade_ms == 0 {
+                    1.0
+                } else {
+                    (1000.0 / consts::FRAME_RATE as f32 / fade_ms as f32).min(1.0)
+                };
+                if self.glow.len() != pixels.len() {
+                    self.glow = vec![[0.0; 3]; pixels.len()];
+                }
+
+                let mut frame = Frame::new(width, height);
+                for (i, index) in pixels.iter().enumerate() {
+                    let target = palette.color(*index);
+                    let glow = &mut self.glow[i];
+
+                    for channel in 0..3 {
+                        let target = target[channel] as f32;
+                        glow[channel] = if *index != 0 {
+                            target
+                        } else {
+                            glow[channel] + (target - glow[channel]) * step
+                        };
+                        frame.pixels[i][channel] = glow[channel].round() as u8;
+                    }
+                }
+
+                frame
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        assert_eq!(
+            PersistenceMode::parse("or"),
+            Some(PersistenceMode::AntiFlicker)
+        );
+        assert_eq!(
+            PersistenceMode::parse("decay"),
+            Some(PersistenceMode::Decay(DEFAULT_FADE_MS))
+        );
+        assert_eq!(
+            PersistenceMode::parse("decay:50"),
+            Some(PersistenceMode::Decay(50))
+        );
+        assert_eq!(PersistenceMode::parse("blend:50"), None);
+    }
+
+    #[test]
+    fn anti_flicker_test() {
+        let palette = Palette::default();
+        let mut persistence = Persistence::new(PersistenceMode::AntiFlicker);
+
+        persistence.apply(&[1, 0], 2, 1, &palette);
+        let frame = persistence.apply(&[0, 1], 2, 1, &palette);
+        assert_eq!(frame.pixels, vec![[255; 3], [255; 3]]);
+
+        let frame = persistence.apply(&[0, 1], 2, 1, &palette);
+        assert_eq!(frame.pixels, vec![[0; 3], [255; 3]]);
+    }
+
+    #[test]
+    fn decay_test() {
+        let palette = Palette::default();
+        // Roughly half the brightness is lost every frame at 60 Hz.
+        let mut persistence = Persistence::new(PersistenceMode::Decay(33));
+
+        persistence.apply(&[1], 1, 1, &palette);
+        let first = persistence.apply(&[0], 1, 1, &palette).pixels[0][0];
+        assert!(first > 0 && first < 255);
+        let second = persistence.apply(&[0], 1, 1, &palette).pixels[0][0];
+        assert!(second < first);
+    }
+}
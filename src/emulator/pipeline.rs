@@ -0,0 +1,92 @@
+use crate::emulator::consts;
+use crate::emulator::effects::Effect;
+use crate::emulator::frame::Frame;
+use crate::emulator::palette::Palette;
+use crate::emulator::persistence::{Persistence, PersistenceMode};
+use crate::emulator::upscale::Upscaler;
+
+// What the framebuffer goes through on its way to the window or a recording:
+// the palette and persistence, then the upscaler, then the effect.
+pub struct Pipeline {
+    palette: Palette,
+    persistence: Persistence,
+    upscaler: Upscaler,
+    effect: Effect,
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline::new()
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline {
+            palette: Palette::default(),
+            persistence: Persistence::new(PersistenceMode::Off),
+            upscaler: Upscaler::None,
+            effect: Effect::None,
+        }
+    }
+
+    // The same settings without the frames persistence remembers, for
+    // another output that is fed its own frames.
+    pub fn fresh(&self) -> Pipeline {
+        Pipeline {
+            palette: self.palette.clone(),
+            persistence: Persistence::new(self.persistence.mode()),
+            upscaler: self.upscaler,
+            effect: self.effect,
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn palette(&self) -> &Palette {
+        &self.palette
+    }
+
+    pub fn set_persistence(&mut self, mode: PersistenceMode) {
+        self.persistence = Persistence::new(mode);
+    }
+
+    pub fn persistence(&self) -> PersistenceMode {
+        self.persistence.mode()
+    }
+
+    pub fn set_upscaler(&mut self, upscaler: Upscaler) {
+        self.upscaler = upscaler;
+    }
+
+    pub fn upscaler(&self) -> Upscaler {
+        self.upscaler
+    }
+
+    pub fn set_effect(&mut self, effect: Effect) {
+        self.effect = effect;
+    }
+
+    pub fn effect(&self) -> Effect {
+        self.effect
+    }
+
+    // Whether the output can change while the framebuffer doesn't.
+    pub fn is_active(&self) -> bool {
+        self.persistence.is_active()
+    }
+
+    pub fn apply(&mut self, pixels: &[u8]) -> Frame {
+        let frame = self.persistence.apply(
+            pixels,
+            consts::DISPLAY_WIDTH,
+            consts::DISPLAY_HEIGHT,
+            &self.palette,
+        );
+        let frame = self.upscaler.apply(&frame);
+
+        self.effect.apply(&frame)
+    }
+}
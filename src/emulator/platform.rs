@@ -0,0 +1,64 @@
+use sdl2::{AudioSubsystem, EventPump, GameControllerSubsystem, Sdl, VideoSubsystem};
+use std::error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlatformError {
+    Init(String),
+    Subsystem(&'static str, String),
+    Window(String),
+}
+
+impl fmt::Display for PlatformError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlatformError::Init(err) => write!(f, "could not initialise SDL: {}", err),
+            PlatformError::Subsystem(name, err) => {
+                write!(f, "could not initialise SDL {}: {}", name, err)
+            }
+            PlatformError::Window(err) => write!(f, "could not create window: {}", err),
+        }
+    }
+}
+
+impl error::Error for PlatformError {}
+
+// The one SDL context of the process. Display, input and anything else
+// talking to SDL get their subsystems from here rather than initialising
+// SDL themselves. SDL stays up while any subsystem handed out is alive.
+pub struct Platform {
+    sdl: Sdl,
+}
+
+impl Platform {
+    pub fn new() -> Result<Platform, PlatformError> {
+        Ok(Platform {
+            sdl: sdl2::init().map_err(PlatformError::Init)?,
+        })
+    }
+
+    pub fn video(&self) -> Result<VideoSubsystem, PlatformError> {
+        self.sdl
+            .video()
+            .map_err(|err| PlatformError::Subsystem("video", err))
+    }
+
+    pub fn audio(&self) -> Result<AudioSubsystem, PlatformError> {
+        self.sdl
+            .audio()
+            .map_err(|err| PlatformError::Subsystem("audio", err))
+    }
+
+    // SDL allows a single event pump, asking for a second one fails.
+    pub fn event_pump(&self) -> Result<EventPump, PlatformError> {
+        self.sdl
+            .event_pump()
+            .map_err(|err| PlatformError::Subsystem("event pump", err))
+    }
+
+    pub fn game_controller(&self) -> Result<GameControllerSubsystem, PlatformError> {
+        self.sdl
+            .game_controller()
+            .map_err(|err| PlatformError::Subsystem("game controller", err))
+    }
+}
@@ -1,5 +1,11 @@
+use crate::emulator::cartridge::Cartridge;
 use crate::emulator::consts;
+use crate::emulator::rom::Rom;
+use crate::emulator::rom_db::{RomDatabase, RomSettings};
 use rand;
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::ops::Range;
 use std::{error, fmt};
 
 use super::consts::FONTSET;
@@ -7,24 +13,120 @@ use super::consts::FONTSET;
 #[derive(Debug)]
 pub enum ProcessorError {
     IoError(std::io::Error),
+    ArchiveError(zip::result::ZipError),
     InvalidRom,
+    InvalidCartridge(String),
+    InvalidSource(String),
+    RomTooLarge(usize, usize),
+    SelfModifyingCode(CodeWrite),
+    InvalidRegister(usize),
+    AddressOutOfRange(usize),
+    StackTooDeep(usize, usize),
+    StackEmpty,
+    InvalidKey(usize),
 }
 
 impl fmt::Display for ProcessorError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             ProcessorError::IoError(err) => write!(f, "{}", err),
-            _ => write!(f, "Hello World"),
+            ProcessorError::ArchiveError(err) => write!(f, "{}", err),
+            ProcessorError::RomTooLarge(size, max) => {
+                write!(f, "ROM is {} bytes, at most {} fit in memory", size, max)
+            }
+            ProcessorError::SelfModifyingCode(write) => write!(f, "{}", write),
+            ProcessorError::InvalidRom => write!(f, "no ROM found"),
+            ProcessorError::InvalidCartridge(err) => write!(f, "invalid cartridge: {}", err),
+            ProcessorError::InvalidSource(err) => write!(f, "invalid Octo source: {}", err),
+            ProcessorError::InvalidRegister(reg) => write!(f, "no register V{:X}", reg),
+            ProcessorError::AddressOutOfRange(addr) => {
+                write!(f, "address {:X} is outside memory", addr)
+            }
+            ProcessorError::StackTooDeep(depth, max) => {
+                write!(
+                    f,
+                    "stack of {} return addresses, at most {} fit",
+                    depth, max
+                )
+            }
+            ProcessorError::StackEmpty => write!(f, "return with an empty stack"),
+            ProcessorError::InvalidKey(key) => write!(f, "no key {:X}", key),
         }
     }
 }
 
 impl error::Error for ProcessorError {}
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelfModifyingCodePolicy {
+    Count,
+    Log,
+    Break,
+}
+
+impl SelfModifyingCodePolicy {
+    pub fn parse(name: &str) -> Option<SelfModifyingCodePolicy> {
+        match name {
+            "count" => Some(SelfModifyingCodePolicy::Count),
+            "log" => Some(SelfModifyingCodePolicy::Log),
+            "break" => Some(SelfModifyingCodePolicy::Break),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CodeWrite {
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl fmt::Display for CodeWrite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:03X}: wrote {:02X} into code at {:03X}",
+            self.pc, self.value, self.addr
+        )
+    }
+}
+
+// The sprite data a DRW read: `height` bytes from `addr`, where a height of
+// zero means a 16x16 SUPER-CHIP sprite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SpriteDraw {
+    pub addr: u16,
+    pub height: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Quirks {
+    pub shift: bool,
+    pub memory_increment_by_x: bool,
+    pub memory_leave_i_unchanged: bool,
+    pub wrap: bool,
+    pub jump: bool,
+    pub vblank: bool,
+    pub logic: bool,
+    // FX0A returns as soon as any key is down instead of waiting for a key
+    // to be pressed and released again.
+    pub wait_key_on_press: bool,
+}
+
+// Where FX0A is in waiting for a key to be pressed and released.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyWait {
+    Idle,
+    Waiting,
+    Pressed(usize),
+}
+
 #[derive(Debug)]
 pub struct Processor {
     registers: [u8; consts::N_REGISTERS],
-    memory: [u8; consts::MEMORY_SIZE],
+    memory: [u8; consts::XO_MEMORY_SIZE],
     pub keys_pressed: [bool; consts::N_KEYS],
     index: u16,
     pc: u16,
@@ -33,12 +135,35 @@ pub struct Processor {
     delay_timer: u8,
     sound_timer: u8,
     pub display: [u8; (consts::DISPLAY_WIDTH * consts::DISPLAY_HEIGHT) as usize],
+    pub self_modifying_code_policy: SelfModifyingCodePolicy,
+    pub quirks: Quirks,
+    display_changed: bool,
+    waiting_for_vblank: bool,
+    fetched: [bool; consts::XO_MEMORY_SIZE],
+    // The first write into each code address, and which addresses have one.
+    code_writes: Vec<CodeWrite>,
+    code_written: Vec<bool>,
+    // The first write into code during the current cycle, for Break.
+    cycle_code_write: Option<CodeWrite>,
+    cycles: u64,
+    // The cycle each address was last written in, plus one; zero if never.
+    last_writes: Vec<u64>,
+    last_draw: Option<SpriteDraw>,
+    drawn_sprites: BTreeSet<SpriteDraw>,
+    key_wait: KeyWait,
+    // Key presses and releases, in order, since FX0A started waiting.
+    key_events: Vec<(usize, bool)>,
+    // The loaded program, for `reset`.
+    rom: Vec<u8>,
+    halted: bool,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Instruction {
     CLS,
     RET,
+    // SUPER-CHIP 00FD: stop the interpreter.
+    EXIT,
     NOP,
     JUMP(u16),
     CALL(u16),
@@ -54,9 +179,9 @@ pub enum Instruction {
     XOR(u8, u8),
     ADDR(u8, u8),
     SUBR(u8, u8),
-    SHR(u8),
+    SHR(u8, u8),
     SUBN(u8, u8),
-    SHL(u8),
+    SHL(u8, u8),
     LDA(u16),
     JUMPV0(u16),
     RND(u8, u8),
@@ -77,11 +202,61 @@ pub enum Instruction {
     LDREG(u8),
 }
 
+// Mnemonics in the style of Cowgod's technical reference.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Instruction::CLS => write!(f, "CLS"),
+            Instruction::RET => write!(f, "RET"),
+            Instruction::EXIT => write!(f, "EXIT"),
+            Instruction::NOP => write!(f, "NOP"),
+            Instruction::JUMP(addr) => write!(f, "JP {:03X}", addr),
+            Instruction::CALL(addr) => write!(f, "CALL {:03X}", addr),
+            Instruction::SE(x, byte) => write!(f, "SE V{:X}, {:02X}", x, byte),
+            Instruction::SER(x, y) => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::SNE(x, byte) => write!(f, "SNE V{:X}, {:02X}", x, byte),
+            Instruction::SNER(x, y) => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LD(x, byte) => write!(f, "LD V{:X}, {:02X}", x, byte),
+            Instruction::ADD(x, byte) => write!(f, "ADD V{:X}, {:02X}", x, byte),
+            Instruction::LDR(x, y) => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::OR(x, y) => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::AND(x, y) => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::XOR(x, y) => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::ADDR(x, y) => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::SUBR(x, y) => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::SHR(x, y) => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SUBN(x, y) => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::SHL(x, y) => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::LDA(addr) => write!(f, "LD I, {:03X}", addr),
+            Instruction::JUMPV0(addr) => write!(f, "JP V0, {:03X}", addr),
+            Instruction::RND(x, byte) => write!(f, "RND V{:X}, {:02X}", x, byte),
+            Instruction::DRW(x, y, n) => write!(f, "DRW V{:X}, V{:X}, {:X}", x, y, n),
+            Instruction::SKP(x) => write!(f, "SKP V{:X}", x),
+            Instruction::SKNP(x) => write!(f, "SKNP V{:X}", x),
+            Instruction::LDT(x) => write!(f, "LD V{:X}, DT", x),
+            Instruction::WKEY(x) => write!(f, "LD V{:X}, K", x),
+            Instruction::SDT(x) => write!(f, "LD DT, V{:X}", x),
+            Instruction::SST(x) => write!(f, "LD ST, V{:X}", x),
+            Instruction::ADDI(x) => write!(f, "ADD I, V{:X}", x),
+            Instruction::LDSPR(x) => write!(f, "LD F, V{:X}", x),
+            Instruction::STBCD(x) => write!(f, "LD B, V{:X}", x),
+            Instruction::STREG(x) => write!(f, "LD [I], V{:X}", x),
+            Instruction::LDREG(x) => write!(f, "LD V{:X}, [I]", x),
+        }
+    }
+}
+
+impl Default for Processor {
+    fn default() -> Processor {
+        Processor::new()
+    }
+}
+
 impl Processor {
     pub fn new() -> Processor {
         Processor {
             registers: [0; consts::N_REGISTERS],
-            memory: [0; consts::MEMORY_SIZE],
+            memory: [0; consts::XO_MEMORY_SIZE],
             keys_pressed: [false; consts::N_KEYS],
             index: 0,
             pc: consts::ROM_START as u16,
@@ -90,26 +265,114 @@ impl Processor {
             delay_timer: 0,
             sound_timer: 0,
             display: [0; (consts::DISPLAY_WIDTH * consts::DISPLAY_HEIGHT) as usize],
+            self_modifying_code_policy: SelfModifyingCodePolicy::Count,
+            quirks: Quirks::default(),
+            display_changed: true,
+            waiting_for_vblank: false,
+            fetched: [false; consts::XO_MEMORY_SIZE],
+            code_writes: Vec::new(),
+            code_written: vec![false; consts::XO_MEMORY_SIZE],
+            cycle_code_write: None,
+            cycles: 0,
+            last_writes: vec![0; consts::XO_MEMORY_SIZE],
+            last_draw: None,
+            drawn_sprites: BTreeSet::new(),
+            key_wait: KeyWait::Idle,
+            key_events: Vec::new(),
+            rom: Vec::new(),
+            halted: false,
         }
     }
 
-    pub fn load_rom(&mut self, path: &str) -> Result<(), ProcessorError> {
-        let rom = std::fs::read(path).map_err(ProcessorError::IoError)?;
+    // Puts the machine back in the state the ROM was loaded into, keeping the
+    // quirks and policies it runs with, and the keys the host still holds.
+    pub fn reset(&mut self) {
+        let rom = std::mem::take(&mut self.rom);
+        *self = Processor {
+            quirks: self.quirks,
+            self_modifying_code_policy: self.self_modifying_code_policy,
+            keys_pressed: self.keys_pressed,
+            ..Processor::new()
+        };
+        self.memory[consts::ROM_START..consts::ROM_START + rom.len()].copy_from_slice(&rom);
+        self.memory[consts::FONTSET_START..consts::FONTSET_END].copy_from_slice(&FONTSET);
+        self.rom = rom;
+    }
 
-        if rom.len() > consts::MEMORY_SIZE - consts::ROM_START {
-            return Err(ProcessorError::InvalidRom);
+    // Octo `.gif` cartridges are recognised by their extension.
+    pub fn load_rom(&mut self, path: &str) -> Result<RomSettings, ProcessorError> {
+        if path.to_lowercase().ends_with(".gif") {
+            return self.load_cartridge(&Cartridge::from_path(path)?);
         }
 
-        self.memory[consts::ROM_START..consts::ROM_START + rom.len()].copy_from_slice(&rom);
+        self.load(&Rom::from_path(path)?)
+    }
+
+    pub fn load_rom_bytes(
+        &mut self,
+        name: &str,
+        rom: &[u8],
+    ) -> Result<RomSettings, ProcessorError> {
+        self.load(&Rom::from_bytes(name, rom.to_vec())?)
+    }
+
+    pub fn load_rom_reader<R: std::io::Read>(
+        &mut self,
+        name: &str,
+        reader: R,
+    ) -> Result<RomSettings, ProcessorError> {
+        self.load(&Rom::from_reader(name, reader)?)
+    }
+
+    pub fn load_cartridge(&mut self, cartridge: &Cartridge) -> Result<RomSettings, ProcessorError> {
+        let mut settings = self.load(&cartridge.rom()?)?;
+        cartridge.apply(&mut settings);
+        self.quirks = settings.quirks;
+
+        Ok(settings)
+    }
+
+    pub fn load(&mut self, rom: &Rom) -> Result<RomSettings, ProcessorError> {
+        if rom.data.len() > rom.format.max_size() {
+            return Err(ProcessorError::RomTooLarge(
+                rom.data.len(),
+                rom.format.max_size(),
+            ));
+        }
+
+        self.memory[consts::ROM_START..consts::ROM_START + rom.data.len()]
+            .copy_from_slice(&rom.data);
         self.memory[consts::FONTSET_START..consts::FONTSET_END].copy_from_slice(&FONTSET);
+        self.fetched.fill(false);
+        self.code_writes.clear();
+        self.code_written.fill(false);
+        self.last_writes.fill(0);
+        self.last_draw = None;
+        self.drawn_sprites.clear();
+        self.key_wait = KeyWait::Idle;
+        self.key_events.clear();
+        self.rom = rom.data.clone();
+        self.halted = false;
+
+        let settings = RomDatabase::shared().lookup(&rom.data, rom.format.platform());
+        self.quirks = settings.quirks;
+
+        Ok(settings)
+    }
 
-        Ok(())
+    // The first write into each address fetched as code since the ROM was
+    // loaded.
+    pub fn self_modifying_code_report(&self) -> &[CodeWrite] {
+        &self.code_writes
     }
 
     pub fn fetch_instruction(&mut self) -> u16 {
+        let second = self.pc.wrapping_add(1);
+        self.fetched[self.pc as usize] = true;
+        self.fetched[second as usize] = true;
         let first_byte: u16 = self.memory[self.pc as usize].into();
-        let second_byte: u16 = self.memory[(self.pc + 1) as usize].into();
-        self.pc += 2;
+        let second_byte: u16 = self.memory[second as usize].into();
+        self.pc = self.pc.wrapping_add(2);
 
         let opcode = (first_byte << 8) | second_byte;
 
@@ -121,6 +384,7 @@ impl Processor {
             0x0 => match opcode & 0xff {
                 0xe0 => Instruction::CLS,
                 0xee => Instruction::RET,
+                0xfd => Instruction::EXIT,
                 _ => Instruction::NOP,
             },
 
@@ -139,9 +403,9 @@ impl Processor {
                 0x3 => Instruction::XOR(((opcode >> 8) & 0xf) as u8, ((opcode >> 4) & 0xf) as u8),
                 0x4 => Instruction::ADDR(((opcode >> 8) & 0xf) as u8, ((opcode >> 4) & 0xf) as u8),
                 0x5 => Instruction::SUBR(((opcode >> 8) & 0xf) as u8, ((opcode >> 4) & 0xf) as u8),
-                0x6 => Instruction::SHR(((opcode >> 8) & 0xf) as u8),
+                0x6 => Instruction::SHR(((opcode >> 8) & 0xf) as u8, ((opcode >> 4) & 0xf) as u8),
                 0x7 => Instruction::SUBN(((opcode >> 8) & 0xf) as u8, ((opcode >> 4) & 0xf) as u8),
-                0xE => Instruction::SHL(((opcode >> 8) & 0xf) as u8),
+                0xE => Instruction::SHL(((opcode >> 8) & 0xf) as u8, ((opcode >> 4) & 0xf) as u8),
                 _ => Instruction::NOP,
             },
 
@@ -181,13 +445,16 @@ impl Processor {
         instruction
     }
 
-    pub fn execute_instruction(&mut self, instruction: Instruction) {
+    // Fails for instructions the machine can't carry out: a return or call
+    // past either end of the stack, or a key that doesn't exist.
+    pub fn execute_instruction(&mut self, instruction: Instruction) -> Result<(), ProcessorError> {
         match instruction {
             Instruction::CLS => self.clear_display(),
-            Instruction::RET => self.ret(),
+            Instruction::RET => self.ret()?,
+            Instruction::EXIT => self.halted = true,
             Instruction::NOP => {}
             Instruction::JUMP(addr) => self.jump(addr),
-            Instruction::CALL(addr) => self.call(addr),
+            Instruction::CALL(addr) => self.call(addr)?,
             Instruction::SE(reg, value) => {
                 self.skip_next_instruction_if(self.registers[reg as usize] == value)
             }
@@ -208,15 +475,15 @@ impl Processor {
             Instruction::XOR(reg1, reg2) => self.xor(reg1, reg2),
             Instruction::ADDR(reg1, reg2) => self.add_registers(reg1, reg2),
             Instruction::SUBR(reg1, reg2) => self.sub_reg(reg1, reg2),
-            Instruction::SHR(reg) => self.shr(reg),
+            Instruction::SHR(reg1, reg2) => self.shr(reg1, reg2),
             Instruction::SUBN(reg1, reg2) => self.subn_reg(reg1, reg2),
-            Instruction::SHL(reg) => self.shl(reg),
+            Instruction::SHL(reg1, reg2) => self.shl(reg1, reg2),
             Instruction::LDA(addr) => self.load_addr(addr),
             Instruction::JUMPV0(addr) => self.jump_addr(addr),
             Instruction::RND(reg, value) => self.rand(reg, value),
             Instruction::DRW(reg1, reg2, n) => self.display(reg1, reg2, n),
-            Instruction::SKP(reg) => self.skp(reg),
-            Instruction::SKNP(reg) => self.sknp(reg),
+            Instruction::SKP(reg) => self.skip_next_instruction_if(self.key(reg)?),
+            Instruction::SKNP(reg) => self.skip_next_instruction_if(!self.key(reg)?),
             Instruction::LDT(reg) => self.load_delay_timer(reg),
             Instruction::WKEY(reg) => self.wait_key(reg),
             Instruction::SDT(reg) => self.set_delay_timer_reg(reg),
@@ -227,38 +494,227 @@ impl Processor {
             Instruction::STREG(reg) => self.save_registers(reg),
             Instruction::LDREG(reg) => self.load_registers(reg),
         }
+
+        Ok(())
+    }
+
+    // Set by EXIT; cycles do nothing from then on until the next load or
+    // reset.
+    pub fn is_halted(&self) -> bool {
+        self.halted
     }
 
     pub fn emulate_cycle(&mut self) -> Result<(), ProcessorError> {
+        if self.waiting_for_vblank || self.halted {
+            return Ok(());
+        }
+
+        self.cycles += 1;
         let opcode = self.fetch_instruction();
         let instruction = Processor::decode_instruction(opcode);
-        self.execute_instruction(instruction);
+        self.execute_instruction(instruction)?;
+
+        match self.cycle_code_write.take() {
+            Some(write) if self.self_modifying_code_policy == SelfModifyingCodePolicy::Break => {
+                Err(ProcessorError::SelfModifyingCode(write))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.waiting_for_vblank = false;
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    // None if `range` reaches past the end of memory.
+    pub fn memory_range(&self, range: Range<usize>) -> Option<&[u8]> {
+        self.memory.get(range)
+    }
+
+    // Instructions executed since power-on.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // The cycle in which `addr` was last written, by the program or `poke`.
+    pub fn last_write(&self, addr: u16) -> Option<u64> {
+        self.last_writes[addr as usize].checked_sub(1)
+    }
+
+    pub fn last_draw(&self) -> Option<SpriteDraw> {
+        self.last_draw
+    }
+
+    // Every distinct sprite drawn since the ROM was loaded.
+    pub fn drawn_sprites(&self) -> &BTreeSet<SpriteDraw> {
+        &self.drawn_sprites
+    }
+
+    // Presses or releases a key. Unlike writing `keys_pressed`, this also
+    // lets FX0A see presses and releases that happen between two cycles.
+    pub fn set_key(&mut self, key: usize, pressed: bool) -> Result<(), ProcessorError> {
+        let state = self
+            .keys_pressed
+            .get_mut(key)
+            .ok_or(ProcessorError::InvalidKey(key))?;
+        if *state == pressed {
+            return Ok(());
+        }
+        *state = pressed;
+        if self.key_wait != KeyWait::Idle {
+            self.key_events.push((key, pressed));
+        }
 
         Ok(())
     }
 
+    // Changes memory from outside the program, e.g. from the hex editor. It
+    // is tracked like any other write but not reported as self-modifying code.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.store(addr, value);
+    }
+
+    pub fn registers(&self) -> &[u8; consts::N_REGISTERS] {
+        &self.registers
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    // Return addresses from the bottom of the stack up.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.sp as usize]
+    }
+
+    // The depth of the stack, which is also where the next CALL goes.
+    pub fn sp(&self) -> u8 {
+        self.sp
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    // The beeper sounds for as long as the sound timer is non-zero.
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // The setters below change the machine from outside the program, for
+    // debuggers, tests and scripts. Any address is fine as PC, I or a return
+    // address: memory is 64 KiB and address arithmetic wraps around it.
+
+    pub fn set_register(&mut self, reg: usize, value: u8) -> Result<(), ProcessorError> {
+        let register = self
+            .registers
+            .get_mut(reg)
+            .ok_or(ProcessorError::InvalidRegister(reg))?;
+        *register = value;
+
+        Ok(())
+    }
+
+    pub fn set_index(&mut self, addr: u16) {
+        self.index = addr;
+    }
+
+    // Also cancels an FX0A wait and resumes a halted program, so that
+    // execution really continues at `addr`.
+    pub fn set_pc(&mut self, addr: u16) {
+        self.pc = addr;
+        self.key_wait = KeyWait::Idle;
+        self.key_events.clear();
+        self.halted = false;
+    }
+
+    // Replaces the return addresses, bottom first, like `stack` returns them.
+    pub fn set_stack(&mut self, stack: &[u16]) -> Result<(), ProcessorError> {
+        if stack.len() > consts::STACK_SIZE {
+            return Err(ProcessorError::StackTooDeep(
+                stack.len(),
+                consts::STACK_SIZE,
+            ));
+        }
+        self.stack[..stack.len()].copy_from_slice(stack);
+        self.stack[stack.len()..].fill(0);
+        self.sp = stack.len() as u8;
+
+        Ok(())
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+
+    // Like `poke` for every byte, nothing is written unless all of them fit.
+    pub fn poke_bytes(&mut self, addr: u16, bytes: &[u8]) -> Result<(), ProcessorError> {
+        let end = addr as usize + bytes.len();
+        if end > self.memory.len() {
+            return Err(ProcessorError::AddressOutOfRange(end - 1));
+        }
+        for (i, byte) in bytes.iter().enumerate() {
+            self.store(addr + i as u16, *byte);
+        }
+
+        Ok(())
+    }
+
+    // Returns whether CLS or DRW touched the framebuffer since the last call.
+    pub fn take_display_changed(&mut self) -> bool {
+        std::mem::replace(&mut self.display_changed, false)
+    }
+
     fn clear_display(&mut self) {
         self.display.fill(0);
+        self.display_changed = true;
     }
 
-    fn ret(&mut self) {
-        self.sp -= 1;
+    fn ret(&mut self) -> Result<(), ProcessorError> {
+        self.sp = self.sp.checked_sub(1).ok_or(ProcessorError::StackEmpty)?;
         self.pc = self.stack[self.sp as usize];
+
+        Ok(())
     }
 
     fn jump(&mut self, addr: u16) {
         self.pc = addr;
     }
 
-    fn call(&mut self, addr: u16) {
-        self.stack[self.sp as usize] = self.pc;
+    fn call(&mut self, addr: u16) -> Result<(), ProcessorError> {
+        let depth = self.sp as usize + 1;
+        let slot = self
+            .stack
+            .get_mut(self.sp as usize)
+            .ok_or(ProcessorError::StackTooDeep(depth, consts::STACK_SIZE))?;
+        *slot = self.pc;
         self.sp += 1;
         self.pc = addr;
+
+        Ok(())
     }
 
     fn skip_next_instruction_if(&mut self, condition: bool) {
         if condition {
-            self.pc += 2;
+            self.pc = self.pc.wrapping_add(2);
         }
     }
 
@@ -276,14 +732,23 @@ impl Processor {
 
     fn or(&mut self, reg1: u8, reg2: u8) {
         self.registers[reg1 as usize] |= self.registers[reg2 as usize];
+        self.reset_flag_if_logic_quirk();
     }
 
     fn and(&mut self, reg1: u8, reg2: u8) {
         self.registers[reg1 as usize] &= self.registers[reg2 as usize];
+        self.reset_flag_if_logic_quirk();
     }
 
     fn xor(&mut self, reg1: u8, reg2: u8) {
         self.registers[reg1 as usize] ^= self.registers[reg2 as usize];
+        self.reset_flag_if_logic_quirk();
+    }
+
+    fn reset_flag_if_logic_quirk(&mut self) {
+        if self.quirks.logic {
+            self.registers[consts::N_REGISTERS - 1] = 0;
+        }
     }
 
     fn add_registers(&mut self, reg1: u8, reg2: u8) {
@@ -293,36 +758,37 @@ impl Processor {
         self.registers[consts::N_REGISTERS - 1] = if overflow { 1 } else { 0 };
     }
 
+    // VF is written last, so that with VF as an operand the flag survives.
     fn sub_reg(&mut self, reg1: u8, reg2: u8) {
-        if self.registers[reg1 as usize] > self.registers[reg2 as usize] {
-            self.registers[consts::N_REGISTERS - 1] = 1;
-        } else {
-            self.registers[consts::N_REGISTERS - 1] = 0;
-        }
-
-        self.registers[reg1 as usize] =
-            self.registers[reg1 as usize].wrapping_sub(self.registers[reg2 as usize]);
+        let (x, y) = (self.registers[reg1 as usize], self.registers[reg2 as usize]);
+        self.registers[reg1 as usize] = x.wrapping_sub(y);
+        self.registers[consts::N_REGISTERS - 1] = if x >= y { 1 } else { 0 };
     }
 
-    fn shr(&mut self, reg: u8) {
-        self.registers[consts::N_REGISTERS - 1] = self.registers[reg as usize] & 0x1;
-        self.registers[reg as usize] >>= 1;
+    fn shr(&mut self, reg1: u8, reg2: u8) {
+        let value = self.shift_source(reg1, reg2);
+        self.registers[reg1 as usize] = value >> 1;
+        self.registers[consts::N_REGISTERS - 1] = value & 0x1;
     }
 
     fn subn_reg(&mut self, reg1: u8, reg2: u8) {
-        if self.registers[reg1 as usize] > self.registers[reg2 as usize] {
-            self.registers[consts::N_REGISTERS - 1] = 0;
-        } else {
-            self.registers[consts::N_REGISTERS - 1] = 1;
-        }
+        let (x, y) = (self.registers[reg1 as usize], self.registers[reg2 as usize]);
+        self.registers[reg1 as usize] = y.wrapping_sub(x);
+        self.registers[consts::N_REGISTERS - 1] = if y >= x { 1 } else { 0 };
+    }
 
-        self.registers[reg1 as usize] =
-            self.registers[reg2 as usize].wrapping_sub(self.registers[reg1 as usize]);
+    fn shl(&mut self, reg1: u8, reg2: u8) {
+        let value = self.shift_source(reg1, reg2);
+        self.registers[reg1 as usize] = value.wrapping_shl(1);
+        self.registers[consts::N_REGISTERS - 1] = value >> 7;
     }
 
-    fn shl(&mut self, reg: u8) {
-        self.registers[consts::N_REGISTERS - 1] = self.registers[reg as usize] >> 7;
-        self.registers[reg as usize] = self.registers[reg as usize].wrapping_shl(1);
+    fn shift_source(&self, reg1: u8, reg2: u8) -> u8 {
+        if self.quirks.shift {
+            self.registers[reg1 as usize]
+        } else {
+            self.registers[reg2 as usize]
+        }
     }
 
     fn load_addr(&mut self, addr: u16) {
@@ -330,22 +796,39 @@ impl Processor {
     }
 
     fn jump_addr(&mut self, addr: u16) {
-        self.pc = addr + self.registers[0] as u16;
+        let reg = if self.quirks.jump {
+            (addr >> 8) & 0xf
+        } else {
+            0
+        };
+        self.pc = addr + self.registers[reg as usize] as u16;
     }
 
     fn display(&mut self, reg1: u8, reg2: u8, n: u8) {
         let x = (self.registers[reg1 as usize] as usize) % consts::DISPLAY_WIDTH;
         let y = (self.registers[reg2 as usize] as usize) % consts::DISPLAY_HEIGHT;
         self.registers[consts::N_REGISTERS - 1] = 0;
+        self.display_changed = true;
+        let draw = SpriteDraw {
+            addr: self.index,
+            height: n,
+        };
+        self.last_draw = Some(draw);
+        self.drawn_sprites.insert(draw);
 
-        for i in 0..n {
-            let sprite_byte = self.memory[self.index as usize + i as usize];
+        for i in 0..n as usize {
+            let Some(row) = self.wrap_or_clip(y + i, consts::DISPLAY_HEIGHT) else {
+                break;
+            };
+            let sprite_byte = self.memory[self.index.wrapping_add(i as u16) as usize];
 
             for j in 0..8 {
+                let Some(column) = self.wrap_or_clip(x + j, consts::DISPLAY_WIDTH) else {
+                    break;
+                };
                 let sprite_pixel = (sprite_byte >> (7 - j)) & 0x1;
 
-                let screen_pixel =
-                    &mut self.display[(y + i as usize) * consts::DISPLAY_WIDTH + (x + j as usize)];
+                let screen_pixel = &mut self.display[row * consts::DISPLAY_WIDTH + column];
 
                 if sprite_pixel > 0 {
                     if screen_pixel > &mut 0 {
@@ -356,18 +839,33 @@ impl Processor {
                 }
             }
         }
+
+        if self.quirks.vblank {
+            self.waiting_for_vblank = true;
+        }
     }
 
-    fn rand(&mut self, reg: u8, value: u8) {
-        self.registers[reg as usize] = rand::random::<u8>() & value;
+    fn wrap_or_clip(&self, position: usize, size: usize) -> Option<usize> {
+        if position < size {
+            Some(position)
+        } else if self.quirks.wrap {
+            Some(position % size)
+        } else {
+            None
+        }
     }
 
-    fn skp(&mut self, reg: u8) {
-        self.skip_next_instruction_if(self.keys_pressed[self.registers[reg as usize] as usize]);
+    fn rand(&mut self, reg: u8, value: u8) {
+        self.registers[reg as usize] = rand::random::<u8>() & value;
     }
 
-    fn sknp(&mut self, reg: u8) {
-        self.skip_next_instruction_if(!self.keys_pressed[self.registers[reg as usize] as usize]);
+    // Whether the key in VX is down, for EX9E and EXA1.
+    fn key(&self, reg: u8) -> Result<bool, ProcessorError> {
+        let key = self.registers[reg as usize] as usize;
+        self.keys_pressed
+            .get(key)
+            .copied()
+            .ok_or(ProcessorError::InvalidKey(key))
     }
 
     fn load_delay_timer(&mut self, reg: u8) {
@@ -375,14 +873,36 @@ impl Processor {
     }
 
     fn wait_key(&mut self, reg: u8) {
-        for (i, key) in self.keys_pressed.iter().enumerate() {
-            if *key {
-                self.registers[reg as usize] = i as u8;
-                return;
+        if self.quirks.wait_key_on_press {
+            for (i, key) in self.keys_pressed.iter().enumerate() {
+                if *key {
+                    self.registers[reg as usize] = i as u8;
+                    return;
+                }
+            }
+
+            self.pc = self.pc.wrapping_sub(2);
+            return;
+        }
+
+        // Keys already down when the wait starts don't count.
+        if self.key_wait == KeyWait::Idle {
+            self.key_wait = KeyWait::Waiting;
+            self.key_events.clear();
+        }
+        for (key, pressed) in std::mem::take(&mut self.key_events) {
+            match (self.key_wait, pressed) {
+                (KeyWait::Waiting, true) => self.key_wait = KeyWait::Pressed(key),
+                (KeyWait::Pressed(waited), false) if waited == key => {
+                    self.registers[reg as usize] = key as u8;
+                    self.key_wait = KeyWait::Idle;
+                    return;
+                }
+                _ => {}
             }
         }
 
-        self.pc -= 2;
+        self.pc = self.pc.wrapping_sub(2);
     }
 
     fn set_delay_timer_reg(&mut self, reg: u8) {
@@ -394,7 +914,7 @@ impl Processor {
     }
 
     fn add_index(&mut self, reg: u8) {
-        self.index += self.registers[reg as usize] as u16;
+        self.index = self.index.wrapping_add(self.registers[reg as usize] as u16);
     }
 
     fn load_sprite(&mut self, reg: u8) {
@@ -402,20 +922,62 @@ impl Processor {
     }
 
     fn store_bcd(&mut self, reg: u8) {
-        self.memory[self.index as usize] = self.registers[reg as usize] / 100;
-        self.memory[self.index as usize + 1] = (self.registers[reg as usize] / 10) % 10;
-        self.memory[self.index as usize + 2] = self.registers[reg as usize] % 10;
+        let value = self.registers[reg as usize];
+        self.write_memory(self.index, value / 100);
+        self.write_memory(self.index.wrapping_add(1), (value / 10) % 10);
+        self.write_memory(self.index.wrapping_add(2), value % 10);
     }
 
     fn save_registers(&mut self, reg: u8) {
         for i in 0..reg + 1 {
-            self.memory[self.index as usize + i as usize] = self.registers[i as usize];
+            self.write_memory(
+                self.index.wrapping_add(i as u16),
+                self.registers[i as usize],
+            );
         }
+        self.advance_index_after_transfer(reg);
+    }
+
+    fn write_memory(&mut self, addr: u16, value: u8) {
+        if self.fetched[addr as usize] {
+            let write = CodeWrite {
+                pc: self.pc.wrapping_sub(2),
+                addr,
+                value,
+            };
+            self.cycle_code_write.get_or_insert(write);
+            // ROMs patching themselves every frame would otherwise flood the
+            // log and grow the report forever.
+            if !self.code_written[addr as usize] {
+                self.code_written[addr as usize] = true;
+                self.code_writes.push(write);
+                if self.self_modifying_code_policy == SelfModifyingCodePolicy::Log {
+                    println!("{}", write);
+                }
+            }
+        }
+
+        self.store(addr, value);
+    }
+
+    // Every memory write ends up here.
+    fn store(&mut self, addr: u16, value: u8) {
+        self.memory[addr as usize] = value;
+        self.last_writes[addr as usize] = self.cycles + 1;
     }
 
     fn load_registers(&mut self, reg: u8) {
         for i in 0..reg + 1 {
-            self.registers[i as usize] = self.memory[self.index as usize + i as usize];
+            self.registers[i as usize] = self.memory[self.index.wrapping_add(i as u16) as usize];
+        }
+        self.advance_index_after_transfer(reg);
+    }
+
+    fn advance_index_after_transfer(&mut self, reg: u8) {
+        if self.quirks.memory_increment_by_x {
+            self.index = self.index.wrapping_add(reg as u16);
+        } else if !self.quirks.memory_leave_i_unchanged {
+            self.index = self.index.wrapping_add(reg as u16 + 1);
         }
     }
 }
@@ -429,4 +991,249 @@ mod tests {
     fn new_processor_test() {
         Processor::new();
     }
+
+    #[test]
+    fn display_changed_test() {
+        let mut processor = Processor::new();
+        assert!(processor.take_display_changed());
+        assert!(!processor.take_display_changed());
+
+        processor
+            .execute_instruction(Instruction::LD(0, 1))
+            .unwrap();
+        assert!(!processor.take_display_changed());
+
+        processor.execute_instruction(Instruction::CLS).unwrap();
+        assert!(processor.take_display_changed());
+    }
+
+    #[test]
+    fn self_modifying_code_test() {
+        let mut processor = Processor::new();
+        // 200: LD I, 200
+        // 202: STREG V0
+        processor.memory[0x200..0x204].copy_from_slice(&[0xA2, 0x00, 0xF0, 0x55]);
+        processor.self_modifying_code_policy = SelfModifyingCodePolicy::Break;
+
+        processor.emulate_cycle().unwrap();
+        assert!(processor.self_modifying_code_report().is_empty());
+
+        match processor.emulate_cycle() {
+            Err(ProcessorError::SelfModifyingCode(write)) => assert_eq!(
+                write,
+                CodeWrite {
+                    pc: 0x202,
+                    addr: 0x200,
+                    value: 0
+                }
+            ),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert_eq!(processor.self_modifying_code_report().len(), 1);
+
+        // Only the first write to each address is reported, but Break still
+        // stops on every one.
+        processor.set_pc(0x202);
+        processor.set_index(0x200);
+        assert!(processor.emulate_cycle().is_err());
+        processor.self_modifying_code_policy = SelfModifyingCodePolicy::Count;
+        processor.set_pc(0x202);
+        processor.set_index(0x200);
+        processor.emulate_cycle().unwrap();
+        assert_eq!(processor.self_modifying_code_report().len(), 1);
+        assert_eq!(
+            SelfModifyingCodePolicy::parse("log"),
+            Some(SelfModifyingCodePolicy::Log)
+        );
+    }
+
+    #[test]
+    fn wait_key_test() {
+        let mut processor = Processor::new();
+        // 200: LD V0, K
+        processor.memory[0x200..0x202].copy_from_slice(&[0xF0, 0x0A]);
+
+        // A key held from before the wait is ignored until pressed again.
+        processor.set_key(0x1, true).unwrap();
+        processor.emulate_cycle().unwrap();
+        processor.set_key(0x5, true).unwrap();
+        processor.emulate_cycle().unwrap();
+        processor.set_key(0x1, false).unwrap();
+        processor.emulate_cycle().unwrap();
+        assert_eq!(processor.pc(), 0x200);
+        processor.set_key(0x5, false).unwrap();
+        processor.emulate_cycle().unwrap();
+        assert_eq!((processor.pc(), processor.registers()[0]), (0x202, 0x5));
+
+        // A tap between two cycles still counts.
+        processor.pc = 0x200;
+        processor.emulate_cycle().unwrap();
+        processor.set_key(0x3, true).unwrap();
+        processor.set_key(0x3, false).unwrap();
+        processor.emulate_cycle().unwrap();
+        assert_eq!((processor.pc(), processor.registers()[0]), (0x202, 0x3));
+
+        processor.quirks.wait_key_on_press = true;
+        processor.pc = 0x200;
+        processor.set_key(0x7, true).unwrap();
+        processor.emulate_cycle().unwrap();
+        assert_eq!((processor.pc(), processor.registers()[0]), (0x202, 0x7));
+    }
+
+    #[test]
+    fn reset_test() {
+        let mut processor = Processor::new();
+        // 200: LD V0, 05
+        // 202: LD I, 300
+        // 204: LD [I], V0
+        processor
+            .load_rom_bytes("test", &[0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55])
+            .unwrap();
+        processor.quirks.jump = true;
+        processor.set_key(0x5, true).unwrap();
+        for _ in 0..3 {
+            processor.emulate_cycle().unwrap();
+        }
+        assert_eq!(processor.memory()[0x300], 0x05);
+
+        processor.reset();
+        assert_eq!(processor.pc(), 0x200);
+        assert_eq!(processor.registers()[0], 0);
+        assert_eq!(processor.memory()[0x300], 0);
+        assert_eq!(processor.memory()[0x200..0x202], [0x60, 0x05]);
+        assert_eq!(processor.memory()[consts::FONTSET_START], FONTSET[0]);
+        assert!(processor.quirks.jump);
+        assert!(processor.keys_pressed[0x5]);
+    }
+
+    #[test]
+    fn unknown_rom_quirks_test() {
+        let mut processor = Processor::new();
+        // 200: LD V1, 81
+        // 202: SHR V0, V1
+        // 204: LD I, 300
+        // 206: LD [I], V1
+        let settings = processor
+            .load_rom_bytes("test", &[0x61, 0x81, 0x80, 0x16, 0xA3, 0x00, 0xF1, 0x55])
+            .unwrap();
+        assert_eq!(settings.platform, "modernChip8");
+        for _ in 0..4 {
+            processor.emulate_cycle().unwrap();
+        }
+        assert_eq!(processor.registers()[0], 0x40);
+        assert_eq!(processor.registers()[0xF], 1);
+        assert_eq!(processor.index(), 0x302);
+    }
+
+    #[test]
+    fn fault_test() {
+        let mut processor = Processor::new();
+        // 200: RET
+        processor.load_rom_bytes("test", &[0x00, 0xEE]).unwrap();
+        assert!(matches!(
+            processor.emulate_cycle(),
+            Err(ProcessorError::StackEmpty)
+        ));
+        assert_eq!(processor.sp(), 0);
+
+        let mut processor = Processor::new();
+        // 200: CALL 200
+        processor.load_rom_bytes("test", &[0x22, 0x00]).unwrap();
+        processor.set_stack(&[0x200; consts::STACK_SIZE]).unwrap();
+        assert!(matches!(
+            processor.emulate_cycle(),
+            Err(ProcessorError::StackTooDeep(17, 16))
+        ));
+
+        let mut processor = Processor::new();
+        // 200: LD V0, 10
+        // 202: SKP V0
+        // 204: SKNP V0
+        processor
+            .load_rom_bytes("test", &[0x60, 0x10, 0xE0, 0x9E, 0xE0, 0xA1])
+            .unwrap();
+        processor.emulate_cycle().unwrap();
+        assert!(matches!(
+            processor.emulate_cycle(),
+            Err(ProcessorError::InvalidKey(0x10))
+        ));
+        assert!(matches!(
+            processor.emulate_cycle(),
+            Err(ProcessorError::InvalidKey(0x10))
+        ));
+    }
+
+    #[test]
+    fn exit_test() {
+        let mut processor = Processor::new();
+        processor
+            .load_rom_bytes("test", &[0x60, 0x01, 0x00, 0xFD, 0x60, 0x02])
+            .unwrap();
+        for _ in 0..3 {
+            processor.emulate_cycle().unwrap();
+        }
+        assert!(processor.is_halted());
+        assert_eq!((processor.pc(), processor.registers()[0]), (0x204, 0x01));
+
+        processor.reset();
+        assert!(!processor.is_halted());
+    }
+
+    #[test]
+    fn state_test() {
+        let mut processor = Processor::new();
+        processor
+            .load_rom_bytes("test", &[0x00, 0xFD, 0x22, 0x10])
+            .unwrap();
+        processor.emulate_cycle().unwrap();
+        assert!(processor.is_halted());
+
+        processor.set_pc(0x202);
+        processor.set_register(0xF, 0x42).unwrap();
+        processor.set_index(0x300);
+        processor.set_delay_timer(9);
+        processor.set_sound_timer(3);
+        processor.poke_bytes(0x300, &[1, 2, 3]).unwrap();
+        processor.emulate_cycle().unwrap();
+        assert_eq!(processor.pc(), 0x210);
+        assert_eq!(processor.stack(), [0x204]);
+        assert_eq!(processor.sp(), 1);
+        assert_eq!(processor.registers()[0xF], 0x42);
+        assert_eq!(processor.index(), 0x300);
+        assert_eq!((processor.delay_timer(), processor.sound_timer()), (9, 3));
+        assert_eq!(processor.memory_range(0x300..0x303), Some(&[1, 2, 3][..]));
+        assert_eq!(processor.last_write(0x302), Some(1));
+
+        processor.set_stack(&[0x400, 0x500]).unwrap();
+        assert_eq!(processor.stack(), [0x400, 0x500]);
+        assert!(processor.memory_range(0xFFFF..0x10001).is_none());
+        assert!(processor.set_register(16, 0).is_err());
+        assert!(processor.set_stack(&[0; 17]).is_err());
+        assert!(matches!(
+            processor.set_key(16, true),
+            Err(ProcessorError::InvalidKey(16))
+        ));
+        assert!(processor.poke_bytes(0xFFFE, &[1, 2, 3]).is_err());
+        assert_eq!(processor.memory()[0xFFFE], 0);
+
+        // Addresses wrap around the end of memory.
+        // 210: RET to FFFE: LD V0, 7B; 0000: LD B, V0 with I at FFFF
+        processor.poke_bytes(0x210, &[0x00, 0xEE]).unwrap();
+        processor.set_stack(&[0xFFFE]).unwrap();
+        processor.poke_bytes(0xFFFE, &[0x60, 0x7B]).unwrap();
+        processor.poke_bytes(0x0000, &[0xF0, 0x33]).unwrap();
+        processor.set_index(0xFFFF);
+        processor.emulate_cycle().unwrap();
+        processor.emulate_cycle().unwrap();
+        processor.emulate_cycle().unwrap();
+        assert_eq!(processor.pc(), 0x0002);
+        assert_eq!(processor.memory()[0xFFFF], 1);
+        assert_eq!(processor.memory_range(0..2), Some(&[2, 3][..]));
+
+        processor.set_index(0xFFFF);
+        processor.set_pc(0xFFFC);
+        processor.poke_bytes(0xFFFC, &[0xF1, 0x65]).unwrap();
+        processor.emulate_cycle().unwrap();
+        assert_eq!(processor.registers()[..2], [1, 2]);
+    }
 }
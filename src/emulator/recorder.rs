@@ -0,0 +1,600 @@
+use crate::emulator::audio::Beeper;
+use crate::emulator::consts;
+use crate::emulator::frame::Frame;
+use crate::emulator::pipeline::Pipeline;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// NeuQuant speed for GIF frames with more than 256 colours, from 1 (best) to
+// 30 (fastest).
+const GIF_QUANTIZE_SPEED: i32 = 10;
+
+// Records every emulated frame as it looks in the window, through its own
+// copy of the display pipeline. `.gif` files get an animated GIF, `.avi` files
+// uncompressed video with the beeper muxed in, and `.y4m` and `.rgb` files
+// uncompressed video (YUV 4:4:4 or bare RGB24 frames) plus a WAV file of the
+// beeper next to them, for example to run
+// `ffmpeg -i demo.y4m -i demo.wav demo.mp4`.
+pub struct Recorder {
+    pipeline: Pipeline,
+    beeper: Beeper,
+    output: Output,
+}
+
+enum Output {
+    Gif(GifRecorder<BufWriter<File>>),
+    Avi(AviWriter<BufWriter<File>>),
+    Video {
+        video: BufWriter<File>,
+        y4m: bool,
+        audio: WavWriter<BufWriter<File>>,
+    },
+}
+
+impl Recorder {
+    pub fn create(path: &Path, pipeline: Pipeline) -> io::Result<Recorder> {
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_lowercase());
+        let width = consts::DISPLAY_WIDTH * consts::RECORDING_SCALE;
+        let height = consts::DISPLAY_HEIGHT * consts::RECORDING_SCALE;
+
+        let output = match extension.as_deref() {
+            Some("gif") => Output::Gif(GifRecorder::new(
+                BufWriter::new(File::create(path)?),
+                width,
+                height,
+            )?),
+            Some("avi") => Output::Avi(AviWriter::new(
+                BufWriter::new(File::create(path)?),
+                width,
+                height,
+            )?),
+            Some(format @ ("y4m" | "rgb")) => {
+                let mut video = BufWriter::new(File::create(path)?);
+                if format == "y4m" {
+                    writeln!(
+                        video,
+                        "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C444",
+                        width,
+                        height,
+                        consts::FRAME_RATE
+                    )?;
+                }
+                let audio = BufWriter::new(File::create(path.with_extension("wav"))?);
+                Output::Video {
+                    video,
+                    y4m: format == "y4m",
+                    audio: WavWriter::new(audio)?,
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("{}: record to .gif, .avi, .y4m or .rgb", path.display()),
+                ))
+            }
+        };
+
+        Ok(Recorder {
+            pipeline,
+            beeper: Beeper::new(),
+            output,
+        })
+    }
+
+    // For following palette, persistence, upscaler and effect changes.
+    pub fn pipeline_mut(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+
+    pub fn record(&mut self, pixels: &[u8], sound: bool) -> io::Result<()> {
+        let frame = self.pipeline.apply(pixels).resize(
+            consts::DISPLAY_WIDTH * consts::RECORDING_SCALE,
+            consts::DISPLAY_HEIGHT * consts::RECORDING_SCALE,
+        );
+
+        match &mut self.output {
+            Output::Gif(gif) => gif.record(frame),
+            Output::Avi(avi) => avi.write_frame(&frame, &self.beeper.frame_samples(sound)),
+            Output::Video { video, y4m, audio } => {
+                if *y4m {
+                    video.write_all(b"FRAME\n")?;
+                    video.write_all(&to_yuv444(&frame))?;
+                } else {
+                    video.write_all(&frame.to_rgb())?;
+                }
+                audio.write_samples(&self.beeper.frame_samples(sound))
+            }
+        }
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        match self.output {
+            Output::Gif(gif) => gif.finish().map(|_| ()),
+            Output::Avi(avi) => avi.finish().map(|_| ()),
+            Output::Video {
+                mut video, audio, ..
+            } => {
+                video.flush()?;
+                audio.finish().map(|_| ())
+            }
+        }
+    }
+}
+
+// Identical consecutive frames are merged into one with a longer delay.
+// Delays are kept in step with the 60 Hz clock even though GIF only counts
+// hundredths of a second. Every frame has its own palette, exact when it has
+// at most 256 colours.
+struct GifRecorder<W: Write> {
+    encoder: gif::Encoder<W>,
+    pending: Option<Frame>,
+    pending_frames: u64,
+    frames: u64,
+    centiseconds: u64,
+}
+
+impl<W: Write> GifRecorder<W> {
+    fn new(writer: W, width: usize, height: usize) -> io::Result<GifRecorder<W>> {
+        let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &[])
+            .map_err(io::Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(io::Error::other)?;
+
+        Ok(GifRecorder {
+            encoder,
+            pending: None,
+            pending_frames: 0,
+            frames: 0,
+            centiseconds: 0,
+        })
+    }
+
+    fn record(&mut self, frame: Frame) -> io::Result<()> {
+        if self.pending.as_ref() != Some(&frame) {
+            self.flush()?;
+            self.pending = Some(frame);
+        }
+        self.pending_frames += 1;
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(pending) = self.pending.take() else {
+            return Ok(());
+        };
+        self.frames += self.pending_frames;
+        self.pending_frames = 0;
+        let centiseconds = self.frames * 100 / consts::FRAME_RATE as u64;
+
+        let mut frame = gif::Frame::from_rgb_speed(
+            pending.width as u16,
+            pending.height as u16,
+            &pending.to_rgb(),
+            GIF_QUANTIZE_SPEED,
+        );
+        frame.delay = (centiseconds - self.centiseconds) as u16;
+        self.centiseconds = centiseconds;
+
+        self.encoder.write_frame(&frame).map_err(io::Error::other)
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.flush()?;
+        self.encoder.into_inner().map_err(io::Error::other)
+    }
+}
+
+// An AVI file with an uncompressed 24-bit video stream and a 16-bit mono PCM
+// stream, each frame's picture followed by its audio. The header is written
+// again by `finish` once the frame and sample counts are known, followed by
+// the index.
+struct AviWriter<W: Write + Seek> {
+    writer: W,
+    width: usize,
+    height: usize,
+    frames: u32,
+    samples: u32,
+    // Id, offset from the "movi" tag and size of every chunk.
+    index: Vec<([u8; 4], u32, u32)>,
+    movi_size: u32,
+}
+
+impl<W: Write + Seek> AviWriter<W> {
+    fn new(writer: W, width: usize, height: usize) -> io::Result<AviWriter<W>> {
+        let mut avi = AviWriter {
+            writer,
+            width,
+            height,
+            frames: 0,
+            samples: 0,
+            index: Vec::new(),
+            // The "movi" tag itself.
+            movi_size: 4,
+        };
+        let header = avi.header(0);
+        avi.writer.write_all(&header)?;
+
+        Ok(avi)
+    }
+
+    // Bottom-up BGR rows padded to four bytes, as in a BMP.
+    fn stride(&self) -> usize {
+        (self.width * 3).div_ceil(4) * 4
+    }
+
+    fn frame_size(&self) -> u32 {
+        (self.stride() * self.height) as u32
+    }
+
+    fn write_frame(&mut self, frame: &Frame, samples: &[i16]) -> io::Result<()> {
+        let mut video = Vec::with_capacity(self.frame_size() as usize);
+        for y in (0..frame.height).rev() {
+            for x in 0..frame.width {
+                let [r, g, b] = frame.get(x, y);
+                video.extend([b, g, r]);
+            }
+            video.resize(video.len().div_ceil(4) * 4, 0);
+        }
+        let audio = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect::<Vec<_>>();
+
+        self.write_chunk(*b"00db", &video)?;
+        self.write_chunk(*b"01wb", &audio)?;
+        self.frames += 1;
+        self.samples += samples.len() as u32;
+
+        Ok(())
+    }
+
+    fn write_chunk(&mut self, id: [u8; 4], data: &[u8]) -> io::Result<()> {
+        let chunk = chunk(id, data);
+        self.writer.write_all(&chunk)?;
+        self.index.push((id, self.movi_size, data.len() as u32));
+        self.movi_size += chunk.len() as u32;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        const AVIIF_KEYFRAME: u32 = 0x10;
+        let mut index = Vec::with_capacity(self.index.len() * 16);
+        for (id, offset, size) in &self.index {
+            index.extend(id);
+            index.extend(AVIIF_KEYFRAME.to_le_bytes());
+            index.extend(offset.to_le_bytes());
+            index.extend(size.to_le_bytes());
+        }
+        let index = chunk(*b"idx1", &index);
+        self.writer.write_all(&index)?;
+
+        let header = self.header(index.len() as u32);
+        self.writer.seek(SeekFrom::Start(0))?;
+        self.writer.write_all(&header)?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+
+    // Everything up to and including the "movi" tag. `index_size` is the
+    // size of the idx1 chunk after the frames.
+    fn header(&self, index_size: u32) -> Vec<u8> {
+        const AVIF_HASINDEX: u32 = 0x10;
+        const AVIF_ISINTERLEAVED: u32 = 0x100;
+        let audio_frame_size = consts::AUDIO_FRAME_SAMPLES as u32 * 2;
+        let byte_rate = consts::AUDIO_SAMPLE_RATE * 2;
+        let (width, height) = (self.width as u32, self.height as u32);
+
+        let avih = words(&[
+            1_000_000 / consts::FRAME_RATE,
+            (self.frame_size() + audio_frame_size) * consts::FRAME_RATE,
+            0,
+            AVIF_HASINDEX | AVIF_ISINTERLEAVED,
+            self.frames,
+            0,
+            2,
+            self.frame_size() + audio_frame_size,
+            width,
+            height,
+            0,
+            0,
+            0,
+            0,
+        ]);
+        let video = [
+            chunk(
+                *b"strh",
+                &stream_header(
+                    *b"vids",
+                    *b"DIB ",
+                    [1, consts::FRAME_RATE],
+                    self.frames,
+                    self.frame_size(),
+                    0,
+                    [width as u16, height as u16],
+                ),
+            ),
+            chunk(
+                *b"strf",
+                &[
+                    words(&[40, width, height]),
+                    halves(&[1, 24]),
+                    words(&[0, self.frame_size(), 0, 0, 0, 0]),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+        let audio = [
+            chunk(
+                *b"strh",
+                &stream_header(
+                    *b"auds",
+                    [0; 4],
+                    [2, byte_rate],
+                    self.samples,
+                    audio_frame_size,
+                    2,
+                    [0, 0],
+                ),
+            ),
+            chunk(
+                *b"strf",
+                &[
+                    halves(&[1, 1]),
+                    words(&[consts::AUDIO_SAMPLE_RATE, byte_rate]),
+                    halves(&[2, 16]),
+                ]
+                .concat(),
+            ),
+        ]
+        .concat();
+        let hdrl = list(
+            *b"hdrl",
+            &[
+                chunk(*b"avih", &avih),
+                list(*b"strl", &video),
+                list(*b"strl", &audio),
+            ]
+            .concat(),
+        );
+
+        let riff_size = 4 + hdrl.len() as u32 + 8 + self.movi_size + index_size;
+        [
+            b"RIFF".to_vec(),
+            riff_size.to_le_bytes().to_vec(),
+            b"AVI ".to_vec(),
+            hdrl,
+            b"LIST".to_vec(),
+            self.movi_size.to_le_bytes().to_vec(),
+            b"movi".to_vec(),
+        ]
+        .concat()
+    }
+}
+
+// An AVI stream header. `rate` is the scale and rate, whose quotient is the
+// number of units per second; `length` counts those units.
+fn stream_header(
+    kind: [u8; 4],
+    handler: [u8; 4],
+    rate: [u32; 2],
+    length: u32,
+    buffer_size: u32,
+    sample_size: u32,
+    size: [u16; 2],
+) -> Vec<u8> {
+    [
+        kind.to_vec(),
+        handler.to_vec(),
+        words(&[0]),
+        halves(&[0, 0]),
+        words(&[
+            0,
+            rate[0],
+            rate[1],
+            0,
+            length,
+            buffer_size,
+            u32::MAX,
+            sample_size,
+        ]),
+        halves(&[0, 0, size[0], size[1]]),
+    ]
+    .concat()
+}
+
+// A RIFF chunk, padded to an even size.
+fn chunk(id: [u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut chunk = [&id[..], &(data.len() as u32).to_le_bytes(), data].concat();
+    if data.len() % 2 == 1 {
+        chunk.push(0);
+    }
+
+    chunk
+}
+
+fn list(kind: [u8; 4], data: &[u8]) -> Vec<u8> {
+    chunk(*b"LIST", &[&kind[..], data].concat())
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn halves(values: &[u16]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+// 16-bit mono PCM. The sizes in the header are filled in by `finish`.
+struct WavWriter<W: Write + Seek> {
+    writer: W,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    fn new(mut writer: W) -> io::Result<WavWriter<W>> {
+        let byte_rate = consts::AUDIO_SAMPLE_RATE * 2;
+        writer.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        writer.write_all(&16u32.to_le_bytes())?;
+        writer.write_all(&1u16.to_le_bytes())?; // PCM
+        writer.write_all(&1u16.to_le_bytes())?; // mono
+        writer.write_all(&consts::AUDIO_SAMPLE_RATE.to_le_bytes())?;
+        writer.write_all(&byte_rate.to_le_bytes())?;
+        writer.write_all(&2u16.to_le_bytes())?; // block align
+        writer.write_all(&16u16.to_le_bytes())?; // bits per sample
+        writer.write_all(b"data\0\0\0\0")?;
+
+        Ok(WavWriter { writer, samples: 0 })
+    }
+
+    fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        for sample in samples {
+            self.writer.write_all(&sample.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+
+        Ok(())
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        let data_size = self.samples * 2;
+        self.writer.seek(SeekFrom::Start(4))?;
+        self.writer.write_all(&(36 + data_size).to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(40))?;
+        self.writer.write_all(&data_size.to_le_bytes())?;
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+// Planar BT.601 studio-swing YCbCr, one plane after another.
+fn to_yuv444(frame: &Frame) -> Vec<u8> {
+    let convert = |[r, g, b]: [u8; 3], coefficients: [f32; 3], offset: f32| {
+        let value =
+            r as f32 * coefficients[0] + g as f32 * coefficients[1] + b as f32 * coefficients[2];
+        (offset + value / 256.0).round() as u8
+    };
+
+    let mut planes = Vec::with_capacity(frame.pixels.len() * 3);
+    for (coefficients, offset) in [
+        ([65.738, 129.057, 25.064], 16.0),
+        ([-37.945, -74.494, 112.439], 128.0),
+        ([112.439, -94.154, -18.285], 128.0),
+    ] {
+        planes.extend(
+            frame
+                .pixels
+                .iter()
+                .map(|pixel| convert(*pixel, coefficients, offset)),
+        );
+    }
+
+    planes
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn gif_recording_test() {
+        let blank = Frame::new(2, 1);
+        let mut lit = blank.clone();
+        lit.set(0, 0, [255, 128, 0]);
+
+        let mut recorder = GifRecorder::new(Vec::new(), 2, 1).unwrap();
+        for frame in [&blank, &blank, &blank, &lit, &blank] {
+            recorder.record(frame.clone()).unwrap();
+        }
+        let gif = recorder.finish().unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(gif.as_slice()).unwrap();
+        let mut delays = Vec::new();
+        let mut pixels = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            delays.push(frame.delay);
+            pixels.push(frame.buffer[..3].to_vec());
+        }
+        // 3, 4 and 5 frames at 60 Hz end at 5, 6 and 8 hundredths.
+        assert_eq!(delays, vec![5, 1, 2]);
+        assert_eq!(pixels[1], vec![255, 128, 0]);
+    }
+
+    #[test]
+    fn avi_test() {
+        let frame = Frame::new(3, 2);
+        let mut beeper = Beeper::new();
+        let mut writer = AviWriter::new(io::Cursor::new(Vec::new()), 3, 2).unwrap();
+        for sound in [true, false] {
+            writer
+                .write_frame(&frame, &beeper.frame_samples(sound))
+                .unwrap();
+        }
+        let avi = writer.finish().unwrap().into_inner();
+
+        let word = |offset: usize| u32::from_le_bytes(avi[offset..offset + 4].try_into().unwrap());
+        assert_eq!(&avi[..4], b"RIFF");
+        assert_eq!(word(4) as usize, avi.len() - 8);
+        // avih: two frames, two streams.
+        assert_eq!(&avi[24..28], b"avih");
+        assert_eq!(word(48), 2);
+        assert_eq!(word(56), 2);
+
+        // Rows of three pixels are padded to 12 bytes.
+        let movi = avi.windows(4).position(|tag| tag == b"movi").unwrap();
+        assert_eq!(&avi[movi + 4..movi + 8], b"00db");
+        assert_eq!(word(movi + 8), 24);
+        assert_eq!(&avi[movi + 36..movi + 40], b"01wb");
+        assert_eq!(word(movi + 40) as usize, consts::AUDIO_FRAME_SAMPLES * 2);
+        assert_eq!(avi[movi + 44..movi + 46], consts::BEEP_VOLUME.to_le_bytes());
+
+        let index = avi.len() - 8 - 4 * 16;
+        assert_eq!(&avi[index..index + 4], b"idx1");
+        assert_eq!(&avi[index + 8..index + 12], b"00db");
+        assert_eq!(word(index + 16), 4);
+    }
+
+    #[test]
+    fn wav_test() {
+        let mut writer = WavWriter::new(io::Cursor::new(Vec::new())).unwrap();
+        let mut beeper = Beeper::new();
+        writer.write_samples(&beeper.frame_samples(true)).unwrap();
+        writer.write_samples(&beeper.frame_samples(false)).unwrap();
+        let wav = writer.finish().unwrap().into_inner();
+
+        let data_size = (consts::AUDIO_FRAME_SAMPLES * 2 * 2) as u32;
+        assert_eq!(wav.len(), 44 + data_size as usize);
+        assert_eq!(wav[4..8], (36 + data_size).to_le_bytes());
+        assert_eq!(wav[40..44], data_size.to_le_bytes());
+        assert_eq!(wav[44..46], consts::BEEP_VOLUME.to_le_bytes());
+        assert_eq!(wav[wav.len() - 2..], [0, 0]);
+    }
+
+    #[test]
+    fn yuv_test() {
+        let frame = Frame {
+            width: 2,
+            height: 1,
+            pixels: vec![[0; 3], [255; 3]],
+        };
+        assert_eq!(to_yuv444(&frame), vec![16, 235, 128, 128, 128, 128]);
+    }
+}
@@ -0,0 +1,264 @@
+use crate::emulator::analysis::ControlFlowGraph;
+use crate::emulator::consts;
+use crate::emulator::processor::ProcessorError;
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RomFormat {
+    Chip8,
+    SuperChip,
+    XoChip,
+    Chip8X,
+}
+
+impl RomFormat {
+    pub fn from_extension(name: &str) -> Option<RomFormat> {
+        let extension = Path::new(name).extension()?.to_str()?.to_lowercase();
+
+        match extension.as_str() {
+            "ch8" => Some(RomFormat::Chip8),
+            "sc8" => Some(RomFormat::SuperChip),
+            "xo8" => Some(RomFormat::XoChip),
+            "c8x" => Some(RomFormat::Chip8X),
+            _ => None,
+        }
+    }
+
+    // Only opcodes reachable from ROM_START are considered, so sprite data
+    // that happens to look like an extended instruction is not counted.
+    pub fn detect(rom: &[u8]) -> RomFormat {
+        if rom.len() > RomFormat::Chip8.max_size() {
+            return RomFormat::XoChip;
+        }
+
+        let cfg = ControlFlowGraph::build(rom);
+        let mut format = RomFormat::Chip8;
+
+        for block in cfg.blocks.values() {
+            for (addr, _) in &block.instructions {
+                let offset = *addr as usize - consts::ROM_START;
+                let opcode = ((rom[offset] as u16) << 8) | rom[offset + 1] as u16;
+
+                if RomFormat::is_xo_chip_opcode(opcode) {
+                    return RomFormat::XoChip;
+                }
+                if RomFormat::is_super_chip_opcode(opcode) {
+                    format = RomFormat::SuperChip;
+                }
+            }
+        }
+
+        format
+    }
+
+    pub fn max_size(&self) -> usize {
+        match self {
+            RomFormat::XoChip => consts::XO_MEMORY_SIZE - consts::ROM_START,
+            _ => consts::MEMORY_SIZE - consts::ROM_START,
+        }
+    }
+
+    pub fn platform(&self) -> &'static str {
+        match self {
+            RomFormat::Chip8 => consts::DEFAULT_PLATFORM,
+            RomFormat::SuperChip => "superchip",
+            RomFormat::XoChip => "xochip",
+            RomFormat::Chip8X => "chip8x",
+        }
+    }
+
+    fn is_super_chip_opcode(opcode: u16) -> bool {
+        matches!(opcode & 0xfff0, 0x00c0)
+            || matches!(opcode, 0x00fb..=0x00ff)
+            || (opcode & 0xf00f == 0xd000)
+            || matches!(opcode & 0xf0ff, 0xf030 | 0xf075 | 0xf085)
+    }
+
+    fn is_xo_chip_opcode(opcode: u16) -> bool {
+        matches!(opcode & 0xf00f, 0x5002 | 0x5003)
+            || matches!(opcode & 0xfff0, 0x00d0)
+            || matches!(opcode, 0xf000 | 0xf002)
+            || matches!(opcode & 0xf0ff, 0xf001 | 0xf03a)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rom {
+    pub name: String,
+    pub format: RomFormat,
+    pub data: Vec<u8>,
+}
+
+impl Rom {
+    pub fn from_path(path: &str) -> Result<Rom, ProcessorError> {
+        let data = std::fs::read(path).map_err(ProcessorError::IoError)?;
+        let name = Path::new(path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        Rom::from_bytes(&name, data)
+    }
+
+    pub fn from_reader<R: Read>(name: &str, mut reader: R) -> Result<Rom, ProcessorError> {
+        let mut data = Vec::new();
+        reader
+            .read_to_end(&mut data)
+            .map_err(ProcessorError::IoError)?;
+
+        Rom::from_bytes(name, data)
+    }
+
+    // ZIP archives are recognised by their magic number and replaced by the
+    // first entry with a known ROM extension, or the first file if none has one.
+    // Whether the ROM fits in memory is checked when it is loaded.
+    pub fn from_bytes(name: &str, data: Vec<u8>) -> Result<Rom, ProcessorError> {
+        if data.starts_with(ZIP_MAGIC) {
+            return Rom::from_zip(data);
+        }
+
+        Ok(Rom::unpacked(name, data))
+    }
+
+    fn unpacked(name: &str, data: Vec<u8>) -> Rom {
+        let format = RomFormat::from_extension(name).unwrap_or_else(|| RomFormat::detect(&data));
+
+        Rom {
+            name: name.to_string(),
+            format,
+            data,
+        }
+    }
+
+    fn from_zip(data: Vec<u8>) -> Result<Rom, ProcessorError> {
+        let mut archive =
+            zip::ZipArchive::new(Cursor::new(data)).map_err(ProcessorError::ArchiveError)?;
+
+        let files: Vec<String> = archive
+            .file_names()
+            .filter(|name| !name.ends_with('/'))
+            .map(String::from)
+            .collect();
+        let name = files
+            .iter()
+            .find(|name| RomFormat::from_extension(name).is_some())
+            .or(files.first())
+            .ok_or(ProcessorError::InvalidRom)?;
+
+        let entry = archive
+            .by_name(name)
+            .map_err(ProcessorError::ArchiveError)?;
+        let name = Path::new(name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        // The size in the header can lie, so the entry is also read no
+        // further than one byte past the largest ROM: enough to tell it is
+        // too large without inflating all of a ZIP bomb.
+        let max_size = RomFormat::XoChip.max_size();
+        if entry.size() > max_size as u64 {
+            return Err(ProcessorError::RomTooLarge(entry.size() as usize, max_size));
+        }
+        let mut data = Vec::new();
+        entry
+            .take(max_size as u64 + 1)
+            .read_to_end(&mut data)
+            .map_err(ProcessorError::IoError)?;
+        // Archives in archives are not unpacked.
+        if data.starts_with(ZIP_MAGIC) {
+            return Err(ProcessorError::InvalidRom);
+        }
+
+        Ok(Rom::unpacked(&name, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::emulator::processor::Processor;
+    use std::io::Write;
+
+    #[test]
+    fn format_detection_test() {
+        assert_eq!(
+            RomFormat::from_extension("game.SC8"),
+            Some(RomFormat::SuperChip)
+        );
+        assert_eq!(RomFormat::from_extension("INVADERS"), None);
+
+        assert_eq!(
+            RomFormat::detect(include_bytes!("../roms/INVADERS")),
+            RomFormat::Chip8
+        );
+        // 200: HIGH, 202: JUMP 202
+        assert_eq!(
+            RomFormat::detect(&[0x00, 0xff, 0x12, 0x02]),
+            RomFormat::SuperChip
+        );
+        // 200: PLANE 3, 202: JUMP 202
+        assert_eq!(
+            RomFormat::detect(&[0xf3, 0x01, 0x12, 0x02]),
+            RomFormat::XoChip
+        );
+        // Unreachable data is ignored.
+        assert_eq!(
+            RomFormat::detect(&[0x12, 0x00, 0x00, 0xff]),
+            RomFormat::Chip8
+        );
+    }
+
+    #[test]
+    fn size_limit_test() {
+        let rom = vec![0; RomFormat::Chip8.max_size() + 1];
+        assert!(matches!(
+            Processor::new().load_rom_bytes("big.ch8", &rom),
+            Err(ProcessorError::RomTooLarge(3585, 3584))
+        ));
+        assert_eq!(
+            Rom::from_bytes("big", rom).unwrap().format,
+            RomFormat::XoChip
+        );
+    }
+
+    #[test]
+    fn zip_test() {
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::SimpleFileOptions::default();
+        archive.start_file("README.txt", options).unwrap();
+        archive.write_all(b"not a rom").unwrap();
+        archive.start_file("games/pong.ch8", options).unwrap();
+        archive.write_all(include_bytes!("../roms/PONG")).unwrap();
+        let data = archive.finish().unwrap().into_inner();
+
+        let rom = Rom::from_reader("pong.zip", Cursor::new(data.clone())).unwrap();
+        assert_eq!(rom.name, "pong.ch8");
+        assert_eq!(rom.format, RomFormat::Chip8);
+        assert_eq!(rom.data, include_bytes!("../roms/PONG"));
+
+        let mut nested = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        nested.start_file("pong.zip", options).unwrap();
+        nested.write_all(&data).unwrap();
+        let nested = nested.finish().unwrap().into_inner();
+        assert!(matches!(
+            Rom::from_bytes("nested.zip", nested),
+            Err(ProcessorError::InvalidRom)
+        ));
+
+        // 1 MiB of zeros compresses to a few KiB.
+        let mut bomb = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let deflated = options.compression_method(zip::CompressionMethod::Deflated);
+        bomb.start_file("bomb.ch8", deflated).unwrap();
+        bomb.write_all(&vec![0; 1 << 20]).unwrap();
+        let bomb = bomb.finish().unwrap().into_inner();
+        assert!(matches!(
+            Rom::from_bytes("bomb.zip", bomb),
+            Err(ProcessorError::RomTooLarge(0x100000, _))
+        ));
+    }
+}
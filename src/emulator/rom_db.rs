@@ -0,0 +1,288 @@
+use crate::emulator::config;
+use crate::emulator::consts;
+use crate::emulator::processor::Quirks;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use sha1::{Digest, Sha1};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Subsets of programs.json and platforms.json from the community
+// chip-8-database, restricted to the ROMs shipped in src/roms.
+const PROGRAMS: &str = include_str!("database/programs.json");
+const PLATFORMS: &str = include_str!("database/platforms.json");
+const USER_PROGRAMS: &str = "programs.json";
+
+#[derive(Debug, Deserialize)]
+struct Program {
+    title: String,
+    roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RomEntry {
+    #[serde(default)]
+    platforms: Vec<String>,
+    tickrate: Option<usize>,
+    colors: Option<Colors>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    // Not part of the community database: a keymap profile for user entries.
+    keymap: Option<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, Map<String, Value>>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Platform {
+    id: String,
+    default_tickrate: usize,
+    quirks: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomSettings {
+    pub title: Option<String>,
+    pub platform: String,
+    pub quirks: Quirks,
+    pub tickrate: usize,
+    pub colors: Option<[[u8; 3]; 2]>,
+    pub keys: Vec<(String, u8)>,
+    pub keymap: Option<String>,
+}
+
+pub struct RomDatabase {
+    roms: HashMap<String, (String, RomEntry)>,
+    platforms: Vec<Platform>,
+}
+
+impl RomDatabase {
+    // The database every ROM load looks up, read once per process so the
+    // user file isn't reloaded with each ROM. A broken user file leaves just
+    // the embedded database.
+    pub fn shared() -> &'static RomDatabase {
+        static DATABASE: OnceLock<RomDatabase> = OnceLock::new();
+        DATABASE.get_or_init(|| {
+            RomDatabase::load().unwrap_or_else(|err| {
+                println!("{}", err);
+                RomDatabase::embedded()
+            })
+        })
+    }
+
+    pub fn embedded() -> RomDatabase {
+        RomDatabase::from_json(PROGRAMS, PLATFORMS).unwrap()
+    }
+
+    // The embedded database with the user's programs.json on top.
+    pub fn load() -> io::Result<RomDatabase> {
+        match config::config_path(USER_PROGRAMS) {
+            Some(path) => RomDatabase::load_from(&path),
+            None => Ok(RomDatabase::embedded()),
+        }
+    }
+
+    pub fn load_from(path: &Path) -> io::Result<RomDatabase> {
+        let mut database = RomDatabase::embedded();
+        let programs = match fs::read_to_string(path) {
+            Ok(programs) => programs,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(database),
+            Err(err) => return Err(err),
+        };
+        database.merge_programs(&programs).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), err),
+            )
+        })?;
+
+        Ok(database)
+    }
+
+    pub fn from_json(programs: &str, platforms: &str) -> Result<RomDatabase, serde_json::Error> {
+        let mut database = RomDatabase {
+            roms: HashMap::new(),
+            platforms: serde_json::from_str(platforms)?,
+        };
+        database.merge_programs(programs)?;
+
+        Ok(database)
+    }
+
+    // Entries for a hash that is already known replace the existing one, which
+    // is how the user file overrides the embedded database.
+    pub fn merge_programs(&mut self, programs: &str) -> Result<(), serde_json::Error> {
+        let programs: Vec<Program> = serde_json::from_str(programs)?;
+
+        for program in programs {
+            for (hash, rom) in program.roms {
+                self.roms
+                    .insert(hash.to_lowercase(), (program.title.clone(), rom));
+            }
+        }
+
+        Ok(())
+    }
+
+    // ROMs missing from the database run on `default_platform`, usually the
+    // one guessed from the ROM format. Plain CHIP-8 ROMs guess modernChip8,
+    // so VX is shifted from VY and I is advanced by FX55/FX65.
+    pub fn lookup(&self, rom: &[u8], default_platform: &str) -> RomSettings {
+        let entry = self.roms.get(&sha1_hex(rom));
+
+        let named = entry.and_then(|(_, rom)| rom.platforms.first());
+        let platform = named.and_then(|id| self.platform(id));
+        if let (Some(id), None) = (named, platform) {
+            println!("Unknown platform {}, using {}", id, default_platform);
+        }
+        let platform = platform
+            .or_else(|| self.platform(default_platform))
+            .or_else(|| self.platform(consts::DEFAULT_PLATFORM));
+
+        let mut quirks = platform.map(|p| p.quirks.clone()).unwrap_or_default();
+        if let (Some((_, rom)), Some(platform)) = (entry, platform) {
+            if let Some(overrides) = rom.quirky_platforms.get(&platform.id) {
+                quirks.extend(overrides.clone());
+            }
+        }
+
+        let mut keys: Vec<(String, u8)> = entry
+            .map(|(_, rom)| rom.keys.clone().into_iter().collect())
+            .unwrap_or_default();
+        keys.sort();
+
+        RomSettings {
+            title: entry.map(|(title, _)| title.clone()),
+            platform: platform
+                .map(|p| p.id.clone())
+                .unwrap_or(consts::DEFAULT_PLATFORM.to_string()),
+            quirks: serde_json::from_value(Value::Object(quirks)).unwrap_or_default(),
+            tickrate: entry
+                .and_then(|(_, rom)| rom.tickrate)
+                .or(platform.map(|p| p.default_tickrate))
+                .unwrap_or(consts::DEFAULT_TICKRATE),
+            colors: entry
+                .and_then(|(_, rom)| rom.colors.as_ref())
+                .and_then(|colors| {
+                    Some([
+                        parse_color(colors.pixels.first()?)?,
+                        parse_color(colors.pixels.get(1)?)?,
+                    ])
+                }),
+            keys,
+            keymap: entry.and_then(|(_, rom)| rom.keymap.clone()),
+        }
+    }
+
+    fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+}
+
+pub fn sha1_hex(rom: &[u8]) -> String {
+    Sha1::digest(rom)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    Some([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn lookup_test() {
+        let database = RomDatabase::from_json(PROGRAMS, PLATFORMS).unwrap();
+
+        let invaders =
+            database.lookup(include_bytes!("../roms/INVADERS"), consts::DEFAULT_PLATFORM);
+        assert_eq!(invaders.title.as_deref(), Some("Space Invaders"));
+        assert_eq!(invaders.platform, "originalChip8");
+        assert!(invaders.quirks.vblank);
+        assert_eq!(invaders.tickrate, 15);
+
+        let unknown = database.lookup(&[0x12, 0x00], consts::DEFAULT_PLATFORM);
+        assert_eq!(unknown.title, None);
+        assert_eq!(unknown.platform, consts::DEFAULT_PLATFORM);
+        assert_eq!(unknown.quirks, Quirks::default());
+    }
+
+    #[test]
+    fn user_overrides_test() {
+        let mut database = RomDatabase::from_json(PROGRAMS, PLATFORMS).unwrap();
+        database
+            .merge_programs(
+                r##"[{
+                    "title": "My Invaders",
+                    "roms": {
+                        "f100197f0f2f05b4f3c8c31ab9c2c3930d3e9571": {
+                            "platforms": ["originalChip8"],
+                            "tickrate": 30,
+                            "keymap": "azerty",
+                            "colors": { "pixels": ["#000000", "#ffaa00"] },
+                            "quirkyPlatforms": { "originalChip8": { "vblank": false } }
+                        }
+                    }
+                }]"##,
+            )
+            .unwrap();
+
+        let invaders =
+            database.lookup(include_bytes!("../roms/INVADERS"), consts::DEFAULT_PLATFORM);
+        assert_eq!(invaders.title.as_deref(), Some("My Invaders"));
+        assert_eq!(invaders.tickrate, 30);
+        assert!(!invaders.quirks.vblank);
+        assert!(invaders.quirks.logic);
+        assert_eq!(invaders.colors, Some([[0, 0, 0], [0xff, 0xaa, 0x00]]));
+        assert_eq!(invaders.keymap.as_deref(), Some("azerty"));
+
+        database
+            .merge_programs(
+                r#"[{
+                    "title": "CHIP-8X Invaders",
+                    "roms": {
+                        "f100197f0f2f05b4f3c8c31ab9c2c3930d3e9571": { "platforms": ["chip8x"] }
+                    }
+                }]"#,
+            )
+            .unwrap();
+        let invaders =
+            database.lookup(include_bytes!("../roms/INVADERS"), consts::DEFAULT_PLATFORM);
+        assert_eq!(invaders.platform, "chip8x");
+        assert!(invaders.quirks.vblank);
+    }
+
+    #[test]
+    fn load_from_test() {
+        let path = std::env::temp_dir().join(format!("chip8-programs-test-{}", std::process::id()));
+        assert!(RomDatabase::load_from(&path).is_ok());
+
+        fs::write(&path, "[{ \"title\": 1 }]").unwrap();
+        let err = RomDatabase::load_from(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().starts_with(&path.display().to_string()));
+    }
+}
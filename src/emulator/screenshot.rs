@@ -0,0 +1,120 @@
+use crate::emulator::consts;
+use crate::emulator::frame::Frame;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Optional tEXt chunks stored alongside the image.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metadata {
+    pub rom: Option<String>,
+    pub frame: Option<u64>,
+}
+
+pub fn write_png<W: Write>(writer: W, frame: &Frame, metadata: &Metadata) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, frame.width as u32, frame.height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.add_text_chunk("Software".to_string(), consts::DISPLAY_TITLE.to_string())?;
+    if let Some(rom) = &metadata.rom {
+        encoder.add_text_chunk("Title".to_string(), rom.clone())?;
+    }
+    if let Some(frame) = metadata.frame {
+        encoder.add_text_chunk("Frame".to_string(), frame.to_string())?;
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&frame.to_rgb())?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+// Writes `frame` into `dir` under a timestamped name and returns its path.
+pub fn save_png(dir: &Path, frame: &Frame, metadata: &Metadata) -> io::Result<PathBuf> {
+    let path = timestamped_path(dir, "png");
+    write_png(BufWriter::new(File::create(&path)?), frame, metadata)?;
+
+    Ok(path)
+}
+
+// A file in `dir` named after the current time, shared with recordings.
+pub fn timestamped_path(dir: &Path, extension: &str) -> PathBuf {
+    dir.join(format!(
+        "chip8-{}.{}",
+        timestamp(SystemTime::now()),
+        extension
+    ))
+}
+
+// UTC as YYYYMMDD-HHMMSS-mmm, so names sort chronologically.
+fn timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+// Howard Hinnant's days-to-date algorithm for the proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month + 2) / 5 + 1) as u32;
+    let month = if month < 10 { month + 3 } else { month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn screenshot_test() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_792_318_245_042);
+        assert_eq!(timestamp(time), "20261018-101045-042");
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+
+        let mut frame = Frame::new(4, 2);
+        frame.set(1, 1, [255, 128, 0]);
+        let metadata = Metadata {
+            rom: Some("INVADERS".to_string()),
+            frame: Some(42),
+        };
+        let mut png = Vec::new();
+        write_png(&mut png, &frame, &metadata).unwrap();
+
+        let mut reader = png::Decoder::new(io::Cursor::new(png)).read_info().unwrap();
+        let text = &reader.info().uncompressed_latin1_text;
+        assert!(text
+            .iter()
+            .any(|chunk| chunk.keyword == "Title" && chunk.text == "INVADERS"));
+        assert!(text
+            .iter()
+            .any(|chunk| chunk.keyword == "Frame" && chunk.text == "42"));
+
+        let mut pixels = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!(pixels, frame.to_rgb());
+    }
+}
@@ -0,0 +1,215 @@
+use crate::emulator::frame::Frame;
+use crate::emulator::processor::{Instruction, Processor, SpriteDraw};
+use std::collections::BTreeSet;
+
+pub const SHEET_COLUMNS: usize = 16;
+const DEFAULT_HEIGHT: u8 = 8;
+// Space around each sprite, in pixels.
+const GAP: usize = 1;
+
+const GRID: [u8; 3] = [24, 24, 24];
+const OFF: [u8; 3] = [48, 48, 48];
+const ON: [u8; 3] = [220, 220, 220];
+const HIGHLIGHT_OFF: [u8; 3] = [24, 56, 96];
+const HIGHLIGHT_ON: [u8; 3] = [64, 160, 255];
+
+// Width and rows of a sprite of `height`, zero being a 16x16 SUPER-CHIP
+// sprite.
+pub fn sprite_size(height: u8) -> (usize, usize) {
+    if height == 0 {
+        (16, 16)
+    } else {
+        (8, height as usize)
+    }
+}
+
+pub fn sprite_bytes(height: u8) -> usize {
+    let (width, rows) = sprite_size(height);
+    width / 8 * rows
+}
+
+// The draw about to be made when PC is at a DRW, the last one made otherwise.
+pub fn current_draw(processor: &Processor) -> Option<SpriteDraw> {
+    let memory = processor.memory();
+    let pc = processor.pc() as usize;
+    if pc + 1 < memory.len() {
+        let opcode = u16::from_be_bytes([memory[pc], memory[pc + 1]]);
+        if let Instruction::DRW(_, _, n) = Processor::decode_instruction(opcode) {
+            return Some(SpriteDraw {
+                addr: processor.index(),
+                height: n,
+            });
+        }
+    }
+
+    processor.last_draw()
+}
+
+// Parses `START:LEN[:HEIGHT]` with a hex start and length and a decimal
+// height, 16 standing for 16x16 sprites.
+pub fn parse_region(spec: &str) -> Option<(u16, usize, u8)> {
+    let mut parts = spec.split(':');
+    let start = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let len = usize::from_str_radix(parts.next()?, 16).ok()?;
+    let height = match parts.next() {
+        None => DEFAULT_HEIGHT,
+        Some("16") => 0,
+        Some(height) => height
+            .parse()
+            .ok()
+            .filter(|height| (1..16).contains(height))?,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((start, len, height))
+}
+
+// Lays `len` bytes from `start` out as consecutive sprites, left to right.
+// Bytes the `highlight` draw reads are shown in blue.
+pub fn render_region(
+    memory: &[u8],
+    start: u16,
+    len: usize,
+    height: u8,
+    columns: usize,
+    highlight: Option<SpriteDraw>,
+) -> Frame {
+    let bytes = sprite_bytes(height);
+    let addrs = (start as usize..start as usize + len.max(1))
+        .step_by(bytes)
+        .map(|addr| addr as u16)
+        .collect::<Vec<_>>();
+
+    grid(memory, &addrs, height, columns, highlight)
+}
+
+// Every sprite in `draws` in its own cell, in address order.
+pub fn sprite_sheet(memory: &[u8], draws: &BTreeSet<SpriteDraw>, columns: usize) -> Frame {
+    if draws.is_empty() {
+        return Frame::new(0, 0);
+    }
+    let (cell_width, cell_rows) = draws
+        .iter()
+        .map(|draw| sprite_size(draw.height))
+        .fold((0, 0), |(width, rows), (w, r)| (width.max(w), rows.max(r)));
+
+    let columns = columns.clamp(1, draws.len());
+    let rows = draws.len().div_ceil(columns);
+    let mut frame = Frame::new(
+        columns * (cell_width + GAP) + GAP,
+        rows * (cell_rows + GAP) + GAP,
+    );
+    frame.pixels.fill(GRID);
+    for (i, draw) in draws.iter().enumerate() {
+        let x = GAP + i % columns * (cell_width + GAP);
+        let y = GAP + i / columns * (cell_rows + GAP);
+        draw_sprite(&mut frame, x, y, memory, draw.addr, draw.height, None);
+    }
+
+    frame
+}
+
+fn grid(
+    memory: &[u8],
+    addrs: &[u16],
+    height: u8,
+    columns: usize,
+    highlight: Option<SpriteDraw>,
+) -> Frame {
+    let (width, rows) = sprite_size(height);
+    let columns = columns.clamp(1, addrs.len().max(1));
+    let grid_rows = addrs.len().div_ceil(columns);
+
+    let mut frame = Frame::new(
+        columns * (width + GAP) + GAP,
+        grid_rows * (rows + GAP) + GAP,
+    );
+    frame.pixels.fill(GRID);
+    for (i, addr) in addrs.iter().enumerate() {
+        let x = GAP + i % columns * (width + GAP);
+        let y = GAP + i / columns * (rows + GAP);
+        draw_sprite(&mut frame, x, y, memory, *addr, height, highlight);
+    }
+
+    frame
+}
+
+fn draw_sprite(
+    frame: &mut Frame,
+    x: usize,
+    y: usize,
+    memory: &[u8],
+    addr: u16,
+    height: u8,
+    highlight: Option<SpriteDraw>,
+) {
+    let (width, rows) = sprite_size(height);
+    let highlighted = |addr: usize| {
+        highlight.is_some_and(|draw| {
+            let start = draw.addr as usize;
+            (start..start + sprite_bytes(draw.height)).contains(&addr)
+        })
+    };
+
+    for dy in 0..rows {
+        for byte in 0..width / 8 {
+            let addr = addr as usize + dy * width / 8 + byte;
+            let value = memory.get(addr).copied().unwrap_or(0);
+            let (on, off) = if highlighted(addr) {
+                (HIGHLIGHT_ON, HIGHLIGHT_OFF)
+            } else {
+                (ON, OFF)
+            };
+            for bit in 0..8 {
+                let color = if value & (0x80 >> bit) != 0 { on } else { off };
+                frame.set(x + byte * 8 + bit, y + dy, color);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::emulator::consts;
+
+    #[test]
+    fn sprites_test() {
+        assert_eq!(parse_region("50:50:5"), Some((0x50, 0x50, 5)));
+        assert_eq!(parse_region("300:40"), Some((0x300, 0x40, 8)));
+        assert_eq!(parse_region("300:40:16"), Some((0x300, 0x40, 0)));
+        assert_eq!(parse_region("300:40:17"), None);
+        assert_eq!(sprite_bytes(0), 32);
+
+        // The font's "0" followed by "1", with "1" highlighted.
+        let mut processor = Processor::new();
+        processor
+            .load_rom_bytes("test", &[0xA0, 0x55, 0xD0, 0x05])
+            .unwrap();
+        processor.emulate_cycle().unwrap();
+        let draw = current_draw(&processor).unwrap();
+        assert_eq!(draw.addr as usize, consts::FONTSET_START + 5);
+
+        let frame = render_region(
+            processor.memory(),
+            consts::FONTSET_START as u16,
+            10,
+            5,
+            SHEET_COLUMNS,
+            Some(draw),
+        );
+        assert_eq!((frame.width, frame.height), (19, 7));
+        assert_eq!(frame.get(1, 1), ON);
+        assert_eq!(frame.get(5, 1), OFF);
+        assert_eq!(frame.get(12, 1), HIGHLIGHT_ON);
+        assert_eq!(frame.get(10, 1), HIGHLIGHT_OFF);
+
+        processor.emulate_cycle().unwrap();
+        let sheet = sprite_sheet(processor.memory(), processor.drawn_sprites(), 4);
+        assert_eq!((sheet.width, sheet.height), (10, 7));
+        assert_eq!(sheet.get(3, 1), ON);
+    }
+}
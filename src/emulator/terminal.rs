@@ -0,0 +1,330 @@
+use crate::emulator::consts;
+use crate::emulator::keymap::Keymap;
+use crate::emulator::palette::Palette;
+use crate::emulator::processor::{Processor, ProcessorError};
+use crate::emulator::{Emulator, RunOutcome};
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags,
+    PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+};
+use crossterm::{cursor, execute, queue, style, terminal};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Most terminals only report presses, so a key counts as held for this many
+// frames after its last press or auto-repeat.
+const KEY_HOLD_FRAMES: u8 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CellMode {
+    // One character per 1x2 pixels, fully coloured.
+    HalfBlock,
+    // One character per 2x4 pixels in a single foreground colour.
+    Braille,
+}
+
+// Runs a ROM in the terminal through crossterm, on a headless emulator.
+pub struct Terminal {
+    emulator: Emulator,
+    mode: CellMode,
+    held: [u8; consts::N_KEYS],
+    key_releases: bool,
+}
+
+impl Terminal {
+    pub fn new(mode: CellMode) -> Terminal {
+        Terminal {
+            emulator: Emulator::headless(),
+            mode,
+            held: [0; consts::N_KEYS],
+            key_releases: false,
+        }
+    }
+
+    pub fn load_rom(&mut self, rom_path: &str) -> Result<(), ProcessorError> {
+        self.emulator.load_rom(rom_path)
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.emulator.set_palette(palette);
+    }
+
+    // Only single-character key names can be told apart in a terminal.
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.emulator.set_keymap(keymap);
+    }
+
+    // Runs until Escape, Ctrl+C or EXIT. The terminal is restored on the way
+    // out, also when the processor fails or something panics.
+    pub fn run(&mut self) -> io::Result<()> {
+        let raw_mode = RawMode::enter()?;
+        self.key_releases = raw_mode.key_releases;
+
+        self.run_loop(&mut io::stdout())
+    }
+
+    fn run_loop(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let frame_duration = Duration::from_secs(1) / consts::FRAME_RATE;
+
+        loop {
+            let frame_start = Instant::now();
+
+            while event::poll(Duration::ZERO)? {
+                if let Event::Key(key) = event::read()? {
+                    if self.handle_key(key) {
+                        return Ok(());
+                    }
+                }
+            }
+            for (key, held) in self.held.iter_mut().enumerate() {
+                if !self.key_releases {
+                    *held = held.saturating_sub(1);
+                }
+                self.emulator
+                    .set_key(key, *held > 0)
+                    .map_err(io::Error::other)?;
+            }
+
+            let outcome = self.emulator.run_frame();
+            self.draw(stdout)?;
+            match outcome {
+                None => {}
+                Some(outcome @ RunOutcome::Error { .. }) => {
+                    return Err(io::Error::other(outcome.to_string()))
+                }
+                Some(_) => return Ok(()),
+            }
+
+            if let Some(remaining) = frame_duration.checked_sub(frame_start.elapsed()) {
+                thread::sleep(remaining);
+            }
+        }
+    }
+
+    // Returns true when the user asked to quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Esc => return true,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return true,
+            KeyCode::Char(c) => {
+                if let Some(key_index) = self.emulator.active_keymap().key(&c.to_string()) {
+                    self.held[key_index] = match key.kind {
+                        KeyEventKind::Release => 0,
+                        _ if self.key_releases => 1,
+                        _ => KEY_HOLD_FRAMES,
+                    };
+                }
+            }
+            _ => {}
+        }
+
+        false
+    }
+
+    fn draw(&mut self, stdout: &mut io::Stdout) -> io::Result<()> {
+        let screen = render(
+            self.emulator.framebuffer(),
+            consts::DISPLAY_WIDTH,
+            consts::DISPLAY_HEIGHT,
+            self.emulator.palette(),
+            self.mode,
+        );
+        let panel = register_panel(self.emulator.processor());
+
+        queue!(stdout, cursor::MoveTo(0, 0))?;
+        for row in 0..screen.len().max(panel.len()) {
+            let line = screen.get(row).map(String::as_str).unwrap_or("");
+            let info = panel.get(row).map(String::as_str).unwrap_or("");
+            queue!(
+                stdout,
+                style::Print(line),
+                style::ResetColor,
+                style::Print("  "),
+                style::Print(info),
+                terminal::Clear(terminal::ClearType::UntilNewLine),
+                style::Print("\r\n")
+            )?;
+        }
+
+        stdout.flush()
+    }
+}
+
+// Raw mode on the alternate screen, left again when dropped so that errors
+// and panics don't leave the terminal unusable.
+struct RawMode {
+    // Terminals speaking the kitty keyboard protocol report releases too.
+    key_releases: bool,
+}
+
+impl RawMode {
+    fn enter() -> io::Result<RawMode> {
+        terminal::enable_raw_mode()?;
+        let mut raw_mode = RawMode {
+            key_releases: false,
+        };
+
+        let mut stdout = io::stdout();
+        execute!(
+            stdout,
+            terminal::EnterAlternateScreen,
+            cursor::Hide,
+            terminal::Clear(terminal::ClearType::All)
+        )?;
+        if terminal::supports_keyboard_enhancement().unwrap_or(false) {
+            execute!(
+                stdout,
+                PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)
+            )?;
+            raw_mode.key_releases = true;
+        }
+
+        Ok(raw_mode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.key_releases {
+            let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(stdout, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn foreground([r, g, b]: [u8; 3]) -> String {
+    format!("\x1b[38;2;{};{};{}m", r, g, b)
+}
+
+fn background([r, g, b]: [u8; 3]) -> String {
+    format!("\x1b[48;2;{};{};{}m", r, g, b)
+}
+
+// Renders an indexed framebuffer as lines of ANSI-coloured text. Colour
+// escapes are only emitted when the colour changes.
+pub fn render(
+    pixels: &[u8],
+    width: usize,
+    height: usize,
+    palette: &Palette,
+    mode: CellMode,
+) -> Vec<String> {
+    let pixel = |x: usize, y: usize| if y < height { pixels[y * width + x] } else { 0 };
+    let mut lines = Vec::new();
+
+    match mode {
+        CellMode::HalfBlock => {
+            for y in (0..height).step_by(2) {
+                let mut line = String::new();
+                let mut colors = None;
+                for x in 0..width {
+                    let cell = (palette.color(pixel(x, y)), palette.color(pixel(x, y + 1)));
+                    if colors != Some(cell) {
+                        line += &foreground(cell.0);
+                        line += &background(cell.1);
+                        colors = Some(cell);
+                    }
+                    line.push('▀');
+                }
+                lines.push(line);
+            }
+        }
+        CellMode::Braille => {
+            // Bit of each dot in the Unicode braille block, by (x, y).
+            const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+            for y in (0..height).step_by(4) {
+                let mut line = background(palette.color(0));
+                let mut color = None;
+                for x in (0..width).step_by(2) {
+                    let mut dots = 0;
+                    let mut index = 0;
+                    for (dx, column) in DOTS.iter().enumerate() {
+                        for (dy, bit) in column.iter().enumerate() {
+                            let value = if x + dx < width {
+                                pixel(x + dx, y + dy)
+                            } else {
+                                0
+                            };
+                            if value != 0 {
+                                dots |= bit;
+                                index = index.max(value);
+                            }
+                        }
+                    }
+                    if dots != 0 && color != Some(index) {
+                        line += &foreground(palette.color(index));
+                        color = Some(index);
+                    }
+                    line.push(char::from_u32(0x2800 + dots).unwrap());
+                }
+                lines.push(line);
+            }
+        }
+    }
+
+    lines
+}
+
+pub(crate) fn register_panel(processor: &Processor) -> Vec<String> {
+    let mut panel = vec![
+        format!("PC {:04X}  I {:04X}", processor.pc(), processor.index()),
+        format!(
+            "DT {:02X}    ST {:02X}",
+            processor.delay_timer(),
+            processor.sound_timer()
+        ),
+    ];
+    for (row, values) in processor.registers().chunks(4).enumerate() {
+        let mut line = String::new();
+        for (column, value) in values.iter().enumerate() {
+            write!(line, "V{:X} {:02X} ", row * 4 + column, value).unwrap();
+        }
+        panel.push(line.trim_end().to_string());
+    }
+    let stack = processor
+        .stack()
+        .iter()
+        .map(|addr| format!("{:04X}", addr))
+        .collect::<Vec<_>>();
+    panel.push(format!("SP {}  {}", stack.len(), stack.join(" ")));
+
+    panel
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let palette = Palette::parse("#000000,#ffffff").unwrap();
+        // 4x4: a lit column on the left and a lit top-right pixel.
+        let pixels = [1, 0, 0, 1, 1, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0];
+
+        let lines = render(&pixels, 4, 4, &palette, CellMode::HalfBlock);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].matches('▀').count(), 4);
+        assert!(lines[0].starts_with("\x1b[38;2;255;255;255m\x1b[48;2;255;255;255m▀"));
+        // Runs of the same colours share one escape sequence.
+        assert_eq!(lines[1].matches("\x1b[").count(), 4);
+
+        let lines = render(&pixels, 4, 4, &palette, CellMode::Braille);
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].ends_with("\u{2847}\u{2808}"));
+    }
+
+    #[test]
+    fn register_panel_test() {
+        let processor = Processor::new();
+        let panel = register_panel(&processor);
+        assert_eq!(panel[0], "PC 0200  I 0000");
+        assert_eq!(panel[2], "V0 00 V1 00 V2 00 V3 00");
+        assert_eq!(panel.len(), 7);
+    }
+}
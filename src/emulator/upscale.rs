@@ -0,0 +1,249 @@
+use crate::emulator::frame::Frame;
+
+pub const UPSCALERS: [&str; 5] = ["none", "scale2x", "scale3x", "hq2x", "xbr"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Upscaler {
+    None,
+    // EPX, also known as Scale2x/AdvMAME2x.
+    Scale2x,
+    Scale3x,
+    // Scale2x whose rounded corners are blended with the centre pixel, in
+    // the spirit of hq2x without its lookup tables.
+    Hq2x,
+    // A 2x filter using the xBR edge weights over a 3x3 neighbourhood.
+    Xbr,
+}
+
+impl Upscaler {
+    pub fn parse(name: &str) -> Option<Upscaler> {
+        match name {
+            "none" => Some(Upscaler::None),
+            "scale2x" | "epx" => Some(Upscaler::Scale2x),
+            "scale3x" => Some(Upscaler::Scale3x),
+            "hq2x" => Some(Upscaler::Hq2x),
+            "xbr" => Some(Upscaler::Xbr),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Upscaler::None => "none",
+            Upscaler::Scale2x => "scale2x",
+            Upscaler::Scale3x => "scale3x",
+            Upscaler::Hq2x => "hq2x",
+            Upscaler::Xbr => "xbr",
+        }
+    }
+
+    pub fn next(&self) -> Upscaler {
+        let current = UPSCALERS
+            .iter()
+            .position(|name| *name == self.name())
+            .unwrap();
+        Upscaler::parse(UPSCALERS[(current + 1) % UPSCALERS.len()]).unwrap()
+    }
+
+    pub fn apply(&self, frame: &Frame) -> Frame {
+        match self {
+            Upscaler::None => frame.clone(),
+            Upscaler::Scale2x => scale2x(frame, false),
+            Upscaler::Scale3x => scale3x(frame),
+            Upscaler::Hq2x => scale2x(frame, true),
+            Upscaler::Xbr => xbr(frame),
+        }
+    }
+}
+
+// The 3x3 neighbourhood of a pixel, row by row, with edges repeated.
+fn neighbourhood(frame: &Frame, x: usize, y: usize) -> [[u8; 3]; 9] {
+    let mut pixels = [[0; 3]; 9];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let nx = (x + i % 3).saturating_sub(1).min(frame.width - 1);
+        let ny = (y + i / 3).saturating_sub(1).min(frame.height - 1);
+        *pixel = frame.get(nx, ny);
+    }
+
+    pixels
+}
+
+fn mix(a: [u8; 3], b: [u8; 3]) -> [u8; 3] {
+    [0, 1, 2].map(|channel| ((a[channel] as u16 + b[channel] as u16) / 2) as u8)
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    (0..3)
+        .map(|channel| (a[channel] as i32 - b[channel] as i32).unsigned_abs())
+        .sum()
+}
+
+fn scale2x(frame: &Frame, blend: bool) -> Frame {
+    let mut output = Frame::new(frame.width * 2, frame.height * 2);
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let [_, b, _, d, e, f, _, h, _] = neighbourhood(frame, x, y);
+            let corner = |edge: [u8; 3]| if blend { mix(e, edge) } else { edge };
+
+            let mut pixels = [e; 4];
+            if b != h && d != f {
+                if d == b {
+                    pixels[0] = corner(d);
+                }
+                if b == f {
+                    pixels[1] = corner(f);
+                }
+                if d == h {
+                    pixels[2] = corner(d);
+                }
+                if h == f {
+                    pixels[3] = corner(f);
+                }
+            }
+
+            for (i, pixel) in pixels.iter().enumerate() {
+                output.set(x * 2 + i % 2, y * 2 + i / 2, *pixel);
+            }
+        }
+    }
+
+    output
+}
+
+fn scale3x(frame: &Frame) -> Frame {
+    let mut output = Frame::new(frame.width * 3, frame.height * 3);
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let [a, b, c, d, e, f, g, h, i] = neighbourhood(frame, x, y);
+
+            let mut pixels = [e; 9];
+            if b != h && d != f {
+                if d == b {
+                    pixels[0] = d;
+                }
+                if (d == b && e != c) || (b == f && e != a) {
+                    pixels[1] = b;
+                }
+                if b == f {
+                    pixels[2] = f;
+                }
+                if (d == b && e != g) || (d == h && e != a) {
+                    pixels[3] = d;
+                }
+                if (b == f && e != i) || (h == f && e != c) {
+                    pixels[5] = f;
+                }
+                if d == h {
+                    pixels[6] = d;
+                }
+                if (d == h && e != i) || (h == f && e != g) {
+                    pixels[7] = h;
+                }
+                if h == f {
+                    pixels[8] = f;
+                }
+            }
+
+            for (n, pixel) in pixels.iter().enumerate() {
+                output.set(x * 3 + n % 3, y * 3 + n / 3, *pixel);
+            }
+        }
+    }
+
+    output
+}
+
+fn xbr(frame: &Frame) -> Frame {
+    let mut output = Frame::new(frame.width * 2, frame.height * 2);
+
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let pixels = neighbourhood(frame, x, y);
+            let e = pixels[4];
+
+            // Each corner is described by the indices of its two edge
+            // neighbours, the diagonal neighbour and the two pixels beyond
+            // the edges, as seen from the centre.
+            let corners = [
+                (1, 3, 0, 2, 6),
+                (1, 5, 2, 0, 8),
+                (7, 3, 6, 8, 0),
+                (7, 5, 8, 6, 2),
+            ];
+            for (n, (v, h, diagonal, v_far, h_far)) in corners.into_iter().enumerate() {
+                let (v, h) = (pixels[v], pixels[h]);
+                let across =
+                    distance(e, pixels[v_far]) + distance(e, pixels[h_far]) + 4 * distance(v, h);
+                let along = distance(v, pixels[v_far])
+                    + distance(h, pixels[h_far])
+                    + 4 * distance(e, pixels[diagonal]);
+
+                let color = if across < along && e != v && e != h {
+                    let edge = if distance(e, v) <= distance(e, h) {
+                        v
+                    } else {
+                        h
+                    };
+                    mix(e, edge)
+                } else {
+                    e
+                };
+                output.set(x * 2 + n % 2, y * 2 + n / 2, color);
+            }
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    const ON: [u8; 3] = [255; 3];
+    const OFF: [u8; 3] = [0; 3];
+
+    // A diagonal staircase:
+    // # .
+    // # #
+    fn staircase() -> Frame {
+        Frame {
+            width: 2,
+            height: 2,
+            pixels: vec![ON, OFF, ON, ON],
+        }
+    }
+
+    #[test]
+    fn scale2x_test() {
+        let output = Upscaler::Scale2x.apply(&staircase());
+        assert_eq!((output.width, output.height), (4, 4));
+        // The empty pixel gets its lower left corner filled in.
+        assert_eq!(output.get(2, 0), OFF);
+        assert_eq!(output.get(2, 1), ON);
+        assert_eq!(output.get(3, 1), OFF);
+
+        let output = Upscaler::Hq2x.apply(&staircase());
+        assert_eq!(output.get(2, 1), [127; 3]);
+    }
+
+    #[test]
+    fn upscalers_keep_flat_frames_flat() {
+        let frame = Frame {
+            width: 3,
+            height: 2,
+            pixels: vec![ON; 6],
+        };
+
+        for name in UPSCALERS {
+            let upscaler = Upscaler::parse(name).unwrap();
+            let output = upscaler.apply(&frame);
+            assert!(output.pixels.iter().all(|pixel| *pixel == ON), "{}", name);
+            assert_ne!(upscaler.next().name(), name);
+        }
+        assert_eq!(Upscaler::Scale3x.apply(&frame).width, 9);
+    }
+}
@@ -1,7 +1,215 @@
-mod emulator;
+#[cfg(feature = "sdl")]
+use chip8_emu::emulator;
+use chip8_emu::emulator::analysis::ControlFlowGraph;
+use chip8_emu::emulator::config::Config;
+use chip8_emu::emulator::debugger::Debugger;
+use chip8_emu::emulator::effects::Effect;
+use chip8_emu::emulator::keymap::Keymap;
+use chip8_emu::emulator::palette::Palette;
+use chip8_emu::emulator::persistence::PersistenceMode;
+use chip8_emu::emulator::processor::{Processor, SelfModifyingCodePolicy};
+use chip8_emu::emulator::screenshot;
+use chip8_emu::emulator::sprites;
+use chip8_emu::emulator::terminal::{CellMode, Terminal};
+use chip8_emu::emulator::upscale::Upscaler;
+use chip8_emu::emulator::Emulator;
+use std::env;
+use std::io;
+use std::path::Path;
 
 fn main() {
-    let mut emulator = emulator::Emulator::new();
-    emulator.load_rom("src/roms/INVADERS");
-    emulator.run();
+    let args: Vec<String> = env::args().collect();
+
+    if args.len() == 3 && args[1] == "--cfg" {
+        match std::fs::read(&args[2]) {
+            Ok(rom) => print!("{}", ControlFlowGraph::build(&rom).to_dot()),
+            Err(err) => println!("{}", err),
+        }
+        return;
+    }
+
+    if args.len() == 5 && args[1] == "--sprites" {
+        if let Err(err) = dump_sprites(&args[2], &args[3], Path::new(&args[4])) {
+            println!("{}", err);
+        }
+        return;
+    }
+
+    let mut options = Options {
+        rom_path: "src/roms/INVADERS",
+        ..Options::default()
+    };
+    let mut cell_mode = None;
+    let mut debug = false;
+    let mut args = args.iter().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--theme" => options.palette = Some(value(arg, args.next(), Palette::theme)),
+            "--palette" => options.palette = Some(value(arg, args.next(), Palette::parse)),
+            "--persistence" => {
+                options.persistence = Some(value(arg, args.next(), PersistenceMode::parse))
+            }
+            "--effect" => options.effect = Some(value(arg, args.next(), Effect::parse)),
+            "--upscale" => options.upscaler = Some(value(arg, args.next(), Upscaler::parse)),
+            "--record" => options.record = Some(value(arg, args.next(), Some)),
+            "--keymap" => options.keymap = Some(value(arg, args.next(), Keymap::profile)),
+            "--self-modifying-code" => {
+                options.code_policy = Some(value(arg, args.next(), SelfModifyingCodePolicy::parse))
+            }
+            "--tui" => cell_mode = Some(CellMode::HalfBlock),
+            "--braille" => cell_mode = Some(CellMode::Braille),
+            "--debug" => debug = true,
+            path => options.rom_path = path,
+        }
+    }
+
+    if debug {
+        return run_debugger(options);
+    }
+    match cell_mode {
+        Some(mode) => run_terminal(mode, options),
+        None => run_window(options),
+    }
+}
+
+// Parses the value following `option`, or exits when it is missing or not
+// one the option takes.
+fn value<'a, T>(
+    option: &str,
+    value: Option<&'a String>,
+    parse: impl Fn(&'a str) -> Option<T>,
+) -> T {
+    let Some(value) = value else {
+        println!("{} needs a value", option);
+        std::process::exit(1);
+    };
+    parse(value).unwrap_or_else(|| {
+        println!("Unknown {} value '{}'", option, value);
+        std::process::exit(1);
+    })
+}
+
+// Settings from the command line, over those of the config file.
+#[derive(Default)]
+#[cfg_attr(not(feature = "sdl"), allow(dead_code))]
+struct Options<'a> {
+    rom_path: &'a str,
+    palette: Option<Palette>,
+    persistence: Option<PersistenceMode>,
+    effect: Option<Effect>,
+    upscaler: Option<Upscaler>,
+    record: Option<&'a str>,
+    keymap: Option<Keymap>,
+    code_policy: Option<SelfModifyingCodePolicy>,
+}
+
+fn run_terminal(mode: CellMode, options: Options) {
+    let mut terminal = Terminal::new(mode);
+    let config = Config::load().unwrap_or_else(|err| {
+        println!("{}", err);
+        Config::default()
+    });
+    if let Some(palette) = config.palette() {
+        terminal.set_palette(palette);
+    }
+    terminal.set_keymap(options.keymap.unwrap_or_else(|| config.keymap()));
+    if let Err(err) = terminal.load_rom(options.rom_path) {
+        println!("{}", err);
+        return;
+    }
+    if let Some(palette) = options.palette {
+        terminal.set_palette(palette);
+    }
+    if let Err(err) = terminal.run() {
+        println!("{}", err);
+    }
+}
+
+fn run_debugger(options: Options) {
+    let mut emulator = Emulator::headless();
+    if let Some(policy) = options.code_policy {
+        emulator.set_self_modifying_code_policy(policy);
+    }
+    if let Err(err) = emulator.load_rom(options.rom_path) {
+        println!("{}", err);
+        return;
+    }
+    let mut debugger = Debugger::new(emulator);
+    if let Err(err) = debugger.run(io::stdin().lock(), io::stdout()) {
+        println!("{}", err);
+    }
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_window(_options: Options) {
+    println!("Built without SDL, only --tui and --braille are available");
+    std::process::exit(1);
+}
+
+#[cfg(feature = "sdl")]
+fn run_window(options: Options) {
+    let mut emulator = match emulator::Emulator::new() {
+        Ok(emulator) => emulator,
+        Err(err) => {
+            println!("{}", err);
+            return;
+        }
+    };
+    if let Some(keymap) = options.keymap {
+        emulator.set_keymap(keymap);
+    }
+    if let Err(err) = emulator.load_rom(options.rom_path) {
+        println!("{}", err);
+    }
+    if let Some(palette) = options.palette {
+        emulator.set_palette(palette);
+    }
+    if let Some(mode) = options.persistence {
+        emulator.set_persistence(mode);
+    }
+    if let Some(upscaler) = options.upscaler {
+        emulator.set_upscaler(upscaler);
+    }
+    if let Some(effect) = options.effect {
+        emulator.set_effect(effect);
+    }
+    if let Some(path) = options.record {
+        if let Err(err) = emulator.start_recording(Path::new(path)) {
+            println!("{}", err);
+        }
+    }
+    if let Some(policy) = options.code_policy {
+        emulator.set_self_modifying_code_policy(policy);
+    }
+    match emulator.run() {
+        emulator::RunOutcome::Quit => {}
+        outcome => println!("{}", outcome),
+    }
+    let code_writes = emulator.self_modifying_code_report().len();
+    if code_writes > 0 {
+        println!("The ROM wrote into {} bytes of its own code", code_writes);
+    }
+}
+
+// Renders a memory region of a freshly loaded ROM, font included, to a PNG.
+fn dump_sprites(rom_path: &str, region: &str, output: &Path) -> Result<(), String> {
+    let (start, len, height) = sprites::parse_region(region)
+        .ok_or_else(|| format!("{}: expected START:LEN[:HEIGHT]", region))?;
+    let mut processor = Processor::new();
+    processor
+        .load_rom(rom_path)
+        .map_err(|err| err.to_string())?;
+
+    let frame = sprites::render_region(
+        processor.memory(),
+        start,
+        len,
+        height,
+        sprites::SHEET_COLUMNS,
+        None,
+    );
+    let file = std::fs::File::create(output).map_err(|err| err.to_string())?;
+    screenshot::write_png(file, &frame, &screenshot::Metadata::default())
+        .map_err(|err| err.to_string())
 }